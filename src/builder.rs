@@ -0,0 +1,152 @@
+use crate::slru::DEFAULT_PROBATIONARY_RATIO;
+use crate::{CacheBuildError, DecayStrategy, FrequencySketch, WTinyLfuCache, WindowPolicy};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+/// The share of the capacity that goes to the window cache unless configured otherwise.
+pub const DEFAULT_WINDOW_RATIO: f64 = 0.01;
+
+/// Configures and creates a [`WTinyLfuCache`].
+///
+/// Only the capacity is required. The sample size defaults to
+/// [`WTinyLfuCache::recommended_sample_size`] of the capacity, and the window gets
+/// [`DEFAULT_WINDOW_RATIO`] of the capacity with the default [`WindowPolicy`].
+///
+/// ```
+/// use wtinylfu::{WTinyLfuCache, WTinyLfuCacheBuilder};
+///
+/// let cache: WTinyLfuCache<u64, u64> = WTinyLfuCacheBuilder::new()
+///     .capacity(1000)
+///     .window_ratio(0.2)
+///     .build()
+///     .unwrap();
+/// assert_eq!(cache.window_cap(), 200);
+/// ```
+#[derive(Clone, Debug)]
+pub struct WTinyLfuCacheBuilder {
+	cap: Option<usize>,
+	sample_size: Option<usize>,
+	window_ratio: f64,
+	protected_ratio: Option<f64>,
+	window_policy: WindowPolicy,
+	decay_strategy: DecayStrategy,
+}
+
+impl WTinyLfuCacheBuilder {
+	/// Creates a builder with the default configuration and no capacity.
+	pub fn new() -> Self {
+		Self {
+			cap: None,
+			sample_size: None,
+			window_ratio: DEFAULT_WINDOW_RATIO,
+			protected_ratio: None,
+			window_policy: WindowPolicy::default(),
+			decay_strategy: DecayStrategy::default(),
+		}
+	}
+
+	/// Sets the maximum number of key-value pairs the cache can store.
+	pub fn capacity(mut self, cap: usize) -> Self {
+		self.cap = Some(cap);
+		self
+	}
+
+	/// Sets the number of accesses a sample consists of before the approximation sketch is aged according to
+	/// the [`DecayStrategy`].
+	pub fn sample_size(mut self, sample_size: usize) -> Self {
+		self.sample_size = Some(sample_size);
+		self
+	}
+
+	/// Sets the share of the capacity that goes to the window cache, the rest goes to the main cache. Larger
+	/// windows favour recency-heavy workloads. Has to be strictly between `0.0` and `1.0`.
+	pub fn window_ratio(mut self, window_ratio: f64) -> Self {
+		self.window_ratio = window_ratio;
+		self
+	}
+
+	/// Sets the share of the main cache's capacity that goes to the protected segment, the rest goes to the
+	/// probationary segment. Defaults to `0.8`. A smaller protected segment favours scan-heavy workloads. Has
+	/// to be strictly between `0.0` and `1.0`. Both segments can hold at least one pair regardless of the
+	/// ratio.
+	pub fn protected_ratio(mut self, protected_ratio: f64) -> Self {
+		self.protected_ratio = Some(protected_ratio);
+		self
+	}
+
+	/// Sets the eviction policy of the window cache.
+	pub fn window_policy(mut self, window_policy: WindowPolicy) -> Self {
+		self.window_policy = window_policy;
+		self
+	}
+
+	/// Sets how the approximation sketch ages its counts at the end of every sample. Defaults to
+	/// [`DecayStrategy::Halve`].
+	pub fn decay_strategy(mut self, decay_strategy: DecayStrategy) -> Self {
+		self.decay_strategy = decay_strategy;
+		self
+	}
+
+	/// Creates the cache, or returns an error if the capacity wasn't set or the configuration is invalid.
+	pub fn build<K: Hash + Eq, V>(self) -> Result<WTinyLfuCache<K, V>, CacheBuildError> {
+		self.build_with_hasher(RandomState::new())
+	}
+
+	/// Works like `build`, but the cache hashes keys with `hash_builder`. See
+	/// [`WTinyLfuCache::with_hasher`].
+	pub fn build_with_hasher<K, V, S>(
+		self,
+		hash_builder: S,
+	) -> Result<WTinyLfuCache<K, V, S>, CacheBuildError>
+	where
+		K: Hash + Eq,
+		S: BuildHasher + Clone,
+	{
+		self.build_with_sketch(hash_builder)
+	}
+
+	/// Works like `build_with_hasher`, but the cache estimates access frequencies with the sketch `C` instead
+	/// of a [`CountMinSketch`]. The sketch is created with `C::with_capacity`.
+	///
+	/// [`CountMinSketch`]: crate::CountMinSketch
+	pub fn build_with_sketch<K, V, S, C>(
+		self,
+		hash_builder: S,
+	) -> Result<WTinyLfuCache<K, V, S, C>, CacheBuildError>
+	where
+		K: Hash + Eq,
+		S: BuildHasher + Clone,
+		C: FrequencySketch<K, S>,
+	{
+		let cap = self.cap.ok_or(CacheBuildError::MissingCapacity)?;
+		if !(self.window_ratio > 0.0 && self.window_ratio < 1.0) {
+			return Err(CacheBuildError::InvalidWindowRatio);
+		}
+		// The default split is kept as the probationary share, so it isn't affected by rounding `1.0 - 0.8`.
+		let probationary_ratio = match self.protected_ratio {
+			Some(ratio) if ratio > 0.0 && ratio < 1.0 => 1.0 - ratio,
+			Some(_) => return Err(CacheBuildError::InvalidProtectedRatio),
+			None => DEFAULT_PROBATIONARY_RATIO,
+		};
+
+		let sample_size = self
+			.sample_size
+			.unwrap_or_else(|| WTinyLfuCache::<K, V>::recommended_sample_size(cap));
+
+		Ok(WTinyLfuCache::from_builder(
+			cap,
+			sample_size,
+			self.window_policy,
+			self.window_ratio,
+			probationary_ratio,
+			self.decay_strategy,
+			hash_builder,
+		))
+	}
+}
+
+impl Default for WTinyLfuCacheBuilder {
+	fn default() -> Self {
+		Self::new()
+	}
+}
@@ -0,0 +1,142 @@
+use crate::{FrequencySketch, WTinyLfuCache};
+use lru::LruCache;
+use std::hash::{BuildHasher, Hash};
+
+/// A minimal interface shared by cache implementations, so that code like benchmark harnesses can be
+/// written generically over them.
+///
+/// The trait is object safe and can be used through `&mut dyn Cache<K, V>`.
+pub trait Cache<K, V> {
+	/// Retrieves a value for the specified key from the cache, counting it as an access.
+	fn get(&mut self, k: &K) -> Option<&V>;
+
+	/// Inserts a new key-value pair or updates it if a pair with the same key exists, returning the old value.
+	fn put(&mut self, k: K, v: V) -> Option<V>;
+
+	/// Removes a key-value pair with the specified key and returns pair's value.
+	fn pop(&mut self, k: &K) -> Option<V>;
+
+	/// Returns a bool indicating whether a key-value pair stored in the cache.
+	fn contains(&self, k: &K) -> bool;
+
+	/// Returns the number of stored key-value pairs.
+	fn len(&self) -> usize;
+
+	/// Returns a bool indicating whether the cache is empty.
+	fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Returns the capacity of the cache.
+	fn cap(&self) -> usize;
+
+	/// Removes all key-value pairs from the cache.
+	fn clear(&mut self);
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Clone, C: FrequencySketch<K, S>> Cache<K, V>
+	for WTinyLfuCache<K, V, S, C>
+{
+	#[inline]
+	fn get(&mut self, k: &K) -> Option<&V> {
+		WTinyLfuCache::get(self, k)
+	}
+
+	#[inline]
+	fn put(&mut self, k: K, v: V) -> Option<V> {
+		WTinyLfuCache::put(self, k, v)
+	}
+
+	#[inline]
+	fn pop(&mut self, k: &K) -> Option<V> {
+		WTinyLfuCache::pop(self, k)
+	}
+
+	#[inline]
+	fn contains(&self, k: &K) -> bool {
+		WTinyLfuCache::contains(self, k)
+	}
+
+	#[inline]
+	fn len(&self) -> usize {
+		WTinyLfuCache::len(self)
+	}
+
+	#[inline]
+	fn cap(&self) -> usize {
+		WTinyLfuCache::cap(self)
+	}
+
+	#[inline]
+	fn clear(&mut self) {
+		WTinyLfuCache::clear(self)
+	}
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> Cache<K, V> for LruCache<K, V, S> {
+	#[inline]
+	fn get(&mut self, k: &K) -> Option<&V> {
+		LruCache::get(self, k)
+	}
+
+	#[inline]
+	fn put(&mut self, k: K, v: V) -> Option<V> {
+		LruCache::put(self, k, v)
+	}
+
+	#[inline]
+	fn pop(&mut self, k: &K) -> Option<V> {
+		LruCache::pop(self, k)
+	}
+
+	#[inline]
+	fn contains(&self, k: &K) -> bool {
+		LruCache::contains(self, k)
+	}
+
+	#[inline]
+	fn len(&self) -> usize {
+		LruCache::len(self)
+	}
+
+	#[inline]
+	fn cap(&self) -> usize {
+		LruCache::cap(self).get()
+	}
+
+	#[inline]
+	fn clear(&mut self) {
+		LruCache::clear(self)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Cache;
+	use crate::WTinyLfuCache;
+	use lru::LruCache;
+	use std::num::NonZeroUsize;
+
+	fn exercise(cache: &mut dyn Cache<i32, &'static str>) {
+		assert!(cache.is_empty());
+		assert_eq!(cache.put(1, "one"), None);
+		assert_eq!(cache.put(2, "two"), None);
+		assert_eq!(cache.put(1, "uno"), Some("one"));
+		assert_eq!(cache.get(&1), Some(&"uno"));
+		assert!(cache.contains(&2));
+		assert_eq!(cache.len(), 2);
+		assert_eq!(cache.cap(), 10);
+
+		assert_eq!(cache.pop(&2), Some("two"));
+		assert!(!cache.contains(&2));
+
+		cache.clear();
+		assert!(cache.is_empty());
+	}
+
+	#[test]
+	fn use_caches_through_trait_object() {
+		exercise(&mut WTinyLfuCache::new(10, 10));
+		exercise(&mut LruCache::new(NonZeroUsize::new(10).unwrap()));
+	}
+}
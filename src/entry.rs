@@ -0,0 +1,113 @@
+use crate::{CountMinSketch, FrequencySketch, WTinyLfuCache};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+/// A view into a single key of a [`WTinyLfuCache`], created by [`WTinyLfuCache::entry`].
+///
+/// The lookup was already recorded when the entry was created, so none of its methods count as accesses.
+pub enum Entry<'a, K: Hash + Eq, V, S = RandomState, C = CountMinSketch<K, S>> {
+	/// The key is stored in the cache.
+	Occupied(OccupiedEntry<'a, K, V, S, C>),
+	/// The key isn't stored in the cache.
+	Vacant(VacantEntry<'a, K, V, S, C>),
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher + Clone, C: FrequencySketch<K, S>> Entry<'a, K, V, S, C> {
+	/// Returns a mutable reference to the stored value, or inserts `default` if the key isn't stored.
+	#[inline]
+	pub fn or_insert(self, default: V) -> &'a mut V {
+		self.or_insert_with(|| default)
+	}
+
+	/// Returns a mutable reference to the stored value, or inserts the value returned by `f` if the key isn't
+	/// stored. `f` is only called then.
+	pub fn or_insert_with<F: FnOnce() -> V>(self, f: F) -> &'a mut V {
+		match self {
+			Self::Occupied(entry) => entry.into_mut(),
+			Self::Vacant(entry) => entry.insert(f()),
+		}
+	}
+
+	/// Calls `f` with the stored value if the key is stored, and returns the entry for further chaining.
+	pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+		if let Self::Occupied(entry) = &mut self {
+			f(entry.get_mut());
+		}
+
+		self
+	}
+
+	/// Returns the key of the entry.
+	#[inline]
+	pub fn key(&self) -> &K {
+		match self {
+			Self::Occupied(entry) => entry.key(),
+			Self::Vacant(entry) => entry.key(),
+		}
+	}
+}
+
+/// An entry for a key that is stored in the cache. See [`Entry`].
+pub struct OccupiedEntry<'a, K: Hash + Eq, V, S = RandomState, C = CountMinSketch<K, S>> {
+	cache: &'a mut WTinyLfuCache<K, V, S, C>,
+	key: K,
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher + Clone, C: FrequencySketch<K, S>>
+	OccupiedEntry<'a, K, V, S, C>
+{
+	pub(crate) fn new(cache: &'a mut WTinyLfuCache<K, V, S, C>, key: K) -> Self {
+		Self { cache, key }
+	}
+
+	/// Returns the key of the entry.
+	#[inline]
+	pub fn key(&self) -> &K {
+		&self.key
+	}
+
+	/// Returns a reference to the stored value.
+	pub fn get(&self) -> &V {
+		self.cache.peek(&self.key).expect("key is stored")
+	}
+
+	/// Returns a mutable reference to the stored value.
+	pub fn get_mut(&mut self) -> &mut V {
+		self.cache.peek_mut(&self.key).expect("key is stored")
+	}
+
+	/// Converts the entry into a mutable reference to the stored value that lives as long as the borrow of the
+	/// cache.
+	pub fn into_mut(self) -> &'a mut V {
+		self.cache.peek_mut(&self.key).expect("key is stored")
+	}
+}
+
+/// An entry for a key that isn't stored in the cache. See [`Entry`].
+pub struct VacantEntry<'a, K: Hash + Eq, V, S = RandomState, C = CountMinSketch<K, S>> {
+	cache: &'a mut WTinyLfuCache<K, V, S, C>,
+	key: K,
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher + Clone, C: FrequencySketch<K, S>>
+	VacantEntry<'a, K, V, S, C>
+{
+	pub(crate) fn new(cache: &'a mut WTinyLfuCache<K, V, S, C>, key: K) -> Self {
+		Self { cache, key }
+	}
+
+	/// Returns the key of the entry.
+	#[inline]
+	pub fn key(&self) -> &K {
+		&self.key
+	}
+
+	/// Inserts the value like `put` does and returns a mutable reference to it.
+	///
+	/// The pair evicted to make room is passed to the eviction callback. The returned reference always points
+	/// at the new value, since new pairs are inserted into the window cache and only older pairs are pushed
+	/// out of it.
+	pub fn insert(self, v: V) -> &'a mut V {
+		self.cache.insert_missing(self.key, v)
+	}
+}
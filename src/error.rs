@@ -0,0 +1,43 @@
+use std::error::Error;
+use std::fmt;
+
+/// An error returned when a cache can't be created with the requested configuration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CacheBuildError {
+	/// The sample size is larger than the allowed multiple of the capacity. The approximation sketch and the
+	/// doorkeeper are sized for the sample, so this is almost always a mistake.
+	SampleSizeTooLarge {
+		/// The requested capacity.
+		cap: usize,
+		/// The requested sample size.
+		sample_size: usize,
+	},
+	/// The builder wasn't given a capacity.
+	MissingCapacity,
+	/// The window ratio isn't strictly between `0.0` and `1.0`.
+	InvalidWindowRatio,
+	/// The protected ratio isn't strictly between `0.0` and `1.0`.
+	InvalidProtectedRatio,
+}
+
+impl fmt::Display for CacheBuildError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::SampleSizeTooLarge { cap, sample_size } => write!(
+				f,
+				"sample size {} is too large for a cache with capacity {}",
+				sample_size, cap
+			),
+			Self::MissingCapacity => write!(f, "no capacity was set"),
+			Self::InvalidWindowRatio => {
+				write!(f, "window ratio must be strictly between 0.0 and 1.0")
+			}
+			Self::InvalidProtectedRatio => {
+				write!(f, "protected ratio must be strictly between 0.0 and 1.0")
+			}
+		}
+	}
+}
+
+impl Error for CacheBuildError {}
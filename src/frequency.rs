@@ -0,0 +1,306 @@
+use crate::sketch::{
+	CountMinSketch, Doorkeeper, FrequencySketch, SKETCH_PROBABILITY, SKETCH_TOLERANCE,
+};
+use std::borrow::Borrow;
+use std::cmp;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+
+const DOORKEEPER_FP_RATE: f64 = 0.01;
+// Sketch counters reaching this value trigger a reset even if the sample isn't complete yet, so hot keys
+// can't saturate their counters and become indistinguishable.
+const COUNTER_HIGH_WATER_MARK: u16 = u16::MAX / 2;
+
+/// How the approximation sketch ages its counts at the end of every sample, configured with
+/// [`WTinyLfuCacheBuilder::decay_strategy`].
+///
+/// [`WTinyLfuCacheBuilder::decay_strategy`]: crate::WTinyLfuCacheBuilder::decay_strategy
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DecayStrategy {
+	/// Zeroes every count, so each sample starts from scratch.
+	Reset,
+	/// Halves every count, like TinyLFU does, so popularity learned in earlier samples fades gradually.
+	#[default]
+	Halve,
+}
+
+/// The access frequency state of a cache: the approximation sketch, the doorkeeper and the progress of the
+/// current sample. Created by [`WTinyLfuCache::clone_frequency_model`] and imported into another cache with
+/// [`WTinyLfuCache::import_frequency`].
+///
+/// The sketch and the doorkeeper hash keys with the hasher of the cache, seeded differently for each of them,
+/// so a deterministic hasher makes the whole model deterministic. The model keeps its hasher when it's
+/// imported into another cache.
+///
+/// The approximation sketch is a [`CountMinSketch`] unless the cache was built with another
+/// [`FrequencySketch`].
+///
+/// [`WTinyLfuCache::clone_frequency_model`]: crate::WTinyLfuCache::clone_frequency_model
+/// [`WTinyLfuCache::import_frequency`]: crate::WTinyLfuCache::import_frequency
+pub struct FrequencyModel<K, S = RandomState, C = CountMinSketch<K, S>> {
+	approximation_sketch: C,
+	doorkeeper: Doorkeeper<K, S>,
+	// Remembers the keys that got past the doorkeeper within the current sample, so each one is only
+	// counted once in `reused_keys`.
+	reused: Doorkeeper<K, S>,
+	sample_size: usize,
+	sample_counter: usize,
+	reused_keys: usize,
+	decay_strategy: DecayStrategy,
+}
+
+impl<K: Hash, S: BuildHasher + Clone, C: FrequencySketch<K, S>> FrequencyModel<K, S, C> {
+	pub(crate) fn new(sample_size: usize, hash_builder: S) -> Self {
+		let sketch_capacity = sample_size * 2;
+
+		Self {
+			approximation_sketch: C::with_capacity(sketch_capacity, hash_builder.clone()),
+			doorkeeper: Doorkeeper::new(sample_size, DOORKEEPER_FP_RATE, hash_builder.clone()),
+			reused: Doorkeeper::new(sample_size, DOORKEEPER_FP_RATE, hash_builder),
+			sample_size,
+			sample_counter: 0,
+			reused_keys: 0,
+			decay_strategy: DecayStrategy::default(),
+		}
+	}
+
+	#[inline]
+	pub(crate) fn decay_strategy(&self) -> DecayStrategy {
+		self.decay_strategy
+	}
+
+	#[inline]
+	pub(crate) fn set_decay_strategy(&mut self, decay_strategy: DecayStrategy) {
+		self.decay_strategy = decay_strategy;
+	}
+
+	/// Records an access to the key. The first access within a sample only sets the key in the doorkeeper,
+	/// subsequent ones increment its count in the approximation sketch.
+	#[inline]
+	pub(crate) fn increment<Q>(&mut self, k: &Q)
+	where
+		K: Borrow<Q>,
+		Q: Hash + ?Sized,
+	{
+		self.increment_by(k, 1);
+	}
+
+	/// Records `times` accesses to the key, producing the same state as calling `increment` `times` times.
+	/// The doorkeeper is only queried once, since it can't forget the key until the next reset.
+	///
+	/// The model is reset when the sample is complete, or earlier if the key's sketch count reaches
+	/// `COUNTER_HIGH_WATER_MARK`. An early reset starts a new sample, so the next scheduled reset happens
+	/// `sample_size` accesses after it.
+	pub(crate) fn increment_by<Q>(&mut self, k: &Q, times: usize)
+	where
+		K: Borrow<Q>,
+		Q: Hash + ?Sized,
+	{
+		let mut in_doorkeeper = self.doorkeeper.check(k);
+
+		for _ in 0..times {
+			if !in_doorkeeper {
+				self.doorkeeper.set(k);
+				in_doorkeeper = true;
+				continue;
+			}
+
+			if self.increment_sketch(k) {
+				in_doorkeeper = false;
+			}
+		}
+	}
+
+	/// Records `frequency` accesses to the key like `increment_by`, capped just below
+	/// `COUNTER_HIGH_WATER_MARK` so seeding a single key never resets the model.
+	pub(crate) fn seed<Q>(&mut self, k: &Q, frequency: u16)
+	where
+		K: Borrow<Q>,
+		Q: Hash + ?Sized,
+	{
+		self.increment_by(k, cmp::min(frequency, COUNTER_HIGH_WATER_MARK - 1) as usize);
+	}
+
+	/// Records an access to the key that always increments its count in the approximation sketch, even if it's
+	/// the first access within the sample. The key is set in the doorkeeper as well.
+	pub(crate) fn increment_counted<Q>(&mut self, k: &Q)
+	where
+		K: Borrow<Q>,
+		Q: Hash + ?Sized,
+	{
+		self.doorkeeper.set(k);
+		self.increment_sketch(k);
+	}
+
+	// Increments the key's count in the approximation sketch and advances the sample. Returns whether the model
+	// was reset.
+	fn increment_sketch<Q>(&mut self, k: &Q) -> bool
+	where
+		K: Borrow<Q>,
+		Q: Hash + ?Sized,
+	{
+		if !self.reused.check(k) {
+			self.reused.set(k);
+			self.reused_keys += 1;
+		}
+
+		let count = self.approximation_sketch.increment(k);
+		self.sample_counter += 1;
+
+		if self.sample_counter >= self.sample_size || count >= COUNTER_HIGH_WATER_MARK {
+			self.reset();
+			return true;
+		}
+
+		false
+	}
+
+	fn reset(&mut self) {
+		match self.decay_strategy {
+			DecayStrategy::Reset => self.approximation_sketch.decay(0.0),
+			DecayStrategy::Halve => self.approximation_sketch.halve(),
+		}
+		self.doorkeeper.clear();
+		self.reused.clear();
+		self.sample_counter = 0;
+		self.reused_keys = 0;
+	}
+
+	/// Multiplies every counter of the approximation sketch by `factor`. The doorkeeper is left untouched.
+	pub(crate) fn decay(&mut self, factor: f64) {
+		self.approximation_sketch.decay(factor);
+	}
+
+	/// Returns the estimated access frequency of the key.
+	#[inline]
+	pub fn estimate<Q>(&self, k: &Q) -> u16
+	where
+		K: Borrow<Q>,
+		Q: Hash + ?Sized,
+	{
+		let estimate = self.approximation_sketch.estimate(k);
+		match self.doorkeeper.check(k) {
+			// A custom sketch can return the largest count.
+			true => estimate.saturating_add(1),
+			false => estimate,
+		}
+	}
+
+	#[inline]
+	pub(crate) fn sample_counter(&self) -> usize {
+		self.sample_counter
+	}
+
+	// Sets the progress of the current sample, e.g. after seeding a new model with previous estimates.
+	#[cfg(feature = "serde")]
+	#[inline]
+	pub(crate) fn set_sample_counter(&mut self, sample_counter: usize) {
+		self.sample_counter = sample_counter;
+	}
+
+	#[inline]
+	pub(crate) fn reused_keys(&self) -> usize {
+		self.reused_keys
+	}
+
+	/// Returns the number of accesses a sample consists of before the sketch is aged. A sample ends early if
+	/// a single key's count reaches half the range of the 16-bit counters, which only happens for sample sizes
+	/// larger than that.
+	#[inline]
+	pub fn sample_size(&self) -> usize {
+		self.sample_size
+	}
+}
+
+impl<K: Hash, S: BuildHasher + Clone> FrequencyModel<K, S> {
+	/// Returns roughly how many bytes the approximation sketch, the doorkeeper and the filter of reused keys
+	/// allocate for `sample_size`.
+	pub(crate) fn memory_overhead(sample_size: usize) -> usize {
+		let (width, depth) = CountMinSketch::<K, S>::dimensions_for(
+			sample_size * 2,
+			SKETCH_PROBABILITY,
+			SKETCH_TOLERANCE,
+		);
+		let sketch_bytes = width * depth * mem::size_of::<u16>();
+		let doorkeeper_bytes = Doorkeeper::<K, S>::bitmap_size(sample_size, DOORKEEPER_FP_RATE);
+
+		sketch_bytes + 2 * doorkeeper_bytes
+	}
+
+	/// Returns the `(width, depth)` dimensions of the approximation sketch.
+	#[inline]
+	pub fn sketch_dimensions(&self) -> (usize, usize) {
+		self.approximation_sketch.dimensions()
+	}
+}
+
+impl<K, S: Clone, C: Clone> Clone for FrequencyModel<K, S, C> {
+	fn clone(&self) -> Self {
+		Self {
+			approximation_sketch: self.approximation_sketch.clone(),
+			doorkeeper: self.doorkeeper.clone(),
+			reused: self.reused.clone(),
+			sample_size: self.sample_size,
+			sample_counter: self.sample_counter,
+			reused_keys: self.reused_keys,
+			decay_strategy: self.decay_strategy,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{FrequencyModel, COUNTER_HIGH_WATER_MARK, DOORKEEPER_FP_RATE};
+	use crate::sketch::Doorkeeper;
+	use std::collections::hash_map::RandomState;
+	use std::mem;
+
+	#[test]
+	fn increment_by_matches_repeated_increments() {
+		let mut repeated: FrequencyModel<u64> = FrequencyModel::new(3, RandomState::new());
+		let mut batched: FrequencyModel<u64> = FrequencyModel::new(3, RandomState::new());
+
+		for _ in 0..5 {
+			repeated.increment(&1);
+		}
+		batched.increment_by(&1, 5);
+
+		assert_eq!(repeated.estimate(&1), batched.estimate(&1));
+		assert_eq!(repeated.sample_counter, batched.sample_counter);
+		assert_eq!(repeated.reused_keys, batched.reused_keys);
+		assert_eq!(batched.sample_counter, 0);
+		assert_eq!(batched.estimate(&1), 2);
+	}
+
+	#[test]
+	fn hot_key_resets_before_sample_is_complete() {
+		let mut model: FrequencyModel<u64> = FrequencyModel::new(100_000, RandomState::new());
+
+		// The first access only sets the doorkeeper bit.
+		model.increment_by(&1, COUNTER_HIGH_WATER_MARK as usize);
+		assert_eq!(model.estimate(&1), COUNTER_HIGH_WATER_MARK);
+		assert_eq!(model.sample_counter, COUNTER_HIGH_WATER_MARK as usize - 1);
+
+		model.increment(&1);
+		assert_eq!(model.sample_counter, 0);
+		assert_eq!(model.estimate(&1), COUNTER_HIGH_WATER_MARK / 2);
+	}
+
+	#[test]
+	fn memory_overhead_matches_allocations() {
+		for sample_size in [1, 10, 100, 1000, 12345] {
+			let model: FrequencyModel<u64> = FrequencyModel::new(sample_size, RandomState::new());
+			let (width, depth) = model.sketch_dimensions();
+			assert_eq!(width, sample_size.max(2).next_power_of_two());
+			let sketch_bytes = width * depth * mem::size_of::<u16>();
+			let doorkeeper_bytes =
+				Doorkeeper::<u64, RandomState>::bitmap_size(sample_size, DOORKEEPER_FP_RATE);
+			assert_eq!(
+				FrequencyModel::<u64, RandomState>::memory_overhead(sample_size),
+				sketch_bytes + 2 * doorkeeper_bytes
+			);
+		}
+	}
+}
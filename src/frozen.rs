@@ -0,0 +1,87 @@
+use crate::{CountMinSketch, FrequencySketch, WTinyLfuCache};
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+/// A read-only W-TinyLFU cache created by [`WTinyLfuCache::freeze`].
+///
+/// Only operations that don't modify entries, their recency or the approximation sketch are available.
+///
+/// ```compile_fail
+/// use wtinylfu::WTinyLfuCache;
+///
+/// let mut cache = WTinyLfuCache::new(10, 10);
+/// cache.put(1, "one");
+///
+/// let mut frozen = cache.freeze();
+/// frozen.put(2, "two");
+/// ```
+pub struct FrozenCache<K: Hash + Eq, V, S = RandomState, C = CountMinSketch<K, S>> {
+	cache: WTinyLfuCache<K, V, S, C>,
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Clone, C: FrequencySketch<K, S>> FrozenCache<K, V, S, C> {
+	pub(crate) fn new(cache: WTinyLfuCache<K, V, S, C>) -> Self {
+		Self { cache }
+	}
+
+	/// Consumes the frozen cache and returns the mutable cache back.
+	pub fn thaw(self) -> WTinyLfuCache<K, V, S, C> {
+		self.cache
+	}
+
+	/// Retrieves a value for the specified key from the cache and returns an immutable reference if it exists.
+	/// If the pair doesn't exist, returns `None`.
+	#[inline]
+	pub fn peek<Q>(&self, k: &Q) -> Option<&V>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		self.cache.peek(k)
+	}
+
+	/// Returns a bool indicating whether a key-value pair stored in the cache.
+	#[inline]
+	pub fn contains<Q>(&self, k: &Q) -> bool
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		self.cache.contains(k)
+	}
+
+	/// Returns the number of stored key-value pairs.
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.cache.len()
+	}
+
+	/// Returns a bool indicating whether the cache is empty.
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.cache.is_empty()
+	}
+
+	/// Returns the capacity of the cache (the maximum number of key-value pairs that the cache can store).
+	#[inline]
+	pub fn cap(&self) -> usize {
+		self.cache.cap()
+	}
+
+	/// Returns the estimated access frequency of the key.
+	#[inline]
+	pub fn estimate_frequency<Q>(&self, k: &Q) -> u16
+	where
+		K: Borrow<Q>,
+		Q: Hash + ?Sized,
+	{
+		self.cache.estimate_frequency(k)
+	}
+
+	/// An iterator visiting all entries in roughly most-recently used order.
+	#[inline]
+	pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+		self.cache.iter()
+	}
+}
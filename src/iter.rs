@@ -0,0 +1,149 @@
+use crate::slot::Slot;
+use crate::{slru, window, CountMinSketch, FrequencySketch, WTinyLfuCache};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::iter::Chain;
+
+/// An iterator over the entries of a [`WTinyLfuCache`], created by [`WTinyLfuCache::iter`].
+pub struct Iter<'a, K, V> {
+	inner: Chain<window::Iter<'a, K, Slot<V>>, slru::Iter<'a, K, Slot<V>>>,
+}
+
+impl<'a, K: Hash + Eq, V> Iter<'a, K, V> {
+	pub(crate) fn new<S: BuildHasher + Clone, C: FrequencySketch<K, S>>(
+		cache: &'a WTinyLfuCache<K, V, S, C>,
+	) -> Self {
+		Self {
+			inner: cache.window_cache.iter().chain(cache.main_cache.iter()),
+		}
+	}
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+	type Item = (&'a K, &'a V);
+
+	#[inline]
+	fn next(&mut self) -> Option<Self::Item> {
+		self.inner.next().map(|(k, entry)| (k, &entry.v))
+	}
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher + Clone, C: FrequencySketch<K, S>> IntoIterator
+	for &'a WTinyLfuCache<K, V, S, C>
+{
+	type Item = (&'a K, &'a V);
+	type IntoIter = Iter<'a, K, V>;
+
+	#[inline]
+	fn into_iter(self) -> Self::IntoIter {
+		self.iter()
+	}
+}
+
+/// An owning iterator over the entries of a [`WTinyLfuCache`], created by its `into_iter` method.
+///
+/// The order is deterministic: the window cache pairs come first, followed by the probationary and then the
+/// protected pairs. Every segment yields its pairs in the order it would evict them, i.e. from the least
+/// recently used pair on, or from the oldest one for a FIFO or SIEVE window. Frequencies aren't consulted, see
+/// [`WTinyLfuCache::into_iter_coldest_first`] for that. The eviction callback isn't called for the pairs.
+pub struct IntoIter<K: Hash + Eq, V, S = RandomState, C = CountMinSketch<K, S>> {
+	cache: WTinyLfuCache<K, V, S, C>,
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Clone, C: FrequencySketch<K, S>> Iterator
+	for IntoIter<K, V, S, C>
+{
+	type Item = (K, V);
+
+	#[inline]
+	fn next(&mut self) -> Option<Self::Item> {
+		pop_next(&mut self.cache)
+	}
+
+	#[inline]
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		let len = self.cache.len();
+		(len, Some(len))
+	}
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Clone, C: FrequencySketch<K, S>> ExactSizeIterator
+	for IntoIter<K, V, S, C>
+{
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Clone, C: FrequencySketch<K, S>> IntoIterator
+	for WTinyLfuCache<K, V, S, C>
+{
+	type Item = (K, V);
+	type IntoIter = IntoIter<K, V, S, C>;
+
+	#[inline]
+	fn into_iter(self) -> Self::IntoIter {
+		IntoIter { cache: self }
+	}
+}
+
+/// A draining iterator over the entries of a [`WTinyLfuCache`], created by [`WTinyLfuCache::drain`].
+///
+/// Yields the pairs in the same order as [`IntoIter`]. The pairs that aren't consumed are removed like `clear`
+/// does when the iterator is dropped.
+// Bounded like the impls of the cache, since `Drop` needs them to clear the segments.
+pub struct Drain<
+	'a,
+	K: Hash + Eq,
+	V,
+	S: BuildHasher + Clone = RandomState,
+	C: FrequencySketch<K, S> = CountMinSketch<K, S>,
+> {
+	cache: &'a mut WTinyLfuCache<K, V, S, C>,
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher + Clone, C: FrequencySketch<K, S>> Drain<'a, K, V, S, C> {
+	pub(crate) fn new(cache: &'a mut WTinyLfuCache<K, V, S, C>) -> Self {
+		Self { cache }
+	}
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Clone, C: FrequencySketch<K, S>> Iterator
+	for Drain<'_, K, V, S, C>
+{
+	type Item = (K, V);
+
+	#[inline]
+	fn next(&mut self) -> Option<Self::Item> {
+		pop_next(self.cache)
+	}
+
+	#[inline]
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		let len = self.cache.len();
+		(len, Some(len))
+	}
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Clone, C: FrequencySketch<K, S>> ExactSizeIterator
+	for Drain<'_, K, V, S, C>
+{
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Clone, C: FrequencySketch<K, S>> Drop
+	for Drain<'_, K, V, S, C>
+{
+	fn drop(&mut self) {
+		self.cache.clear();
+	}
+}
+
+// Pops the least recently used window cache pair, or the least recently used main cache pair once the window is
+// empty.
+fn pop_next<K: Hash + Eq, V, S: BuildHasher + Clone, C: FrequencySketch<K, S>>(
+	cache: &mut WTinyLfuCache<K, V, S, C>,
+) -> Option<(K, V)> {
+	let (k, entry) = match cache.window_cache.pop_lru() {
+		Some(pair) => pair,
+		None => cache.main_cache.pop_lru()?,
+	};
+
+	Some((k, entry.v))
+}
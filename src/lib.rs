@@ -1,382 +1,4503 @@
 #![forbid(unsafe_code)]
 
+mod builder;
+mod cache;
+mod entry;
+mod error;
+mod frequency;
+mod frozen;
+mod iter;
+#[cfg(feature = "serde")]
+mod serialize;
+mod sketch;
+mod slot;
 mod slru;
+mod stats;
+mod window;
+
+pub use builder::{WTinyLfuCacheBuilder, DEFAULT_WINDOW_RATIO};
+pub use cache::Cache;
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use error::CacheBuildError;
+pub use frequency::{DecayStrategy, FrequencyModel};
+pub use frozen::FrozenCache;
+pub use iter::{Drain, IntoIter, Iter};
+pub use sketch::{CountMinSketch, FrequencySketch};
+pub use stats::Stats;
+pub use window::WindowPolicy;
 
-use bloomfilter::Bloom;
-use count_min_sketch::CountMinSketch16;
 use lru::LruCache;
+use slot::Slot;
 use slru::SlruCache;
+use std::borrow::Borrow;
 use std::cmp;
-use std::hash::Hash;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
 use std::num::NonZeroUsize;
+use window::WindowCache;
+
+/// The largest multiple of the capacity `try_new` accepts as the sample size.
+pub const DEFAULT_MAX_SAMPLE_SIZE_RATIO: usize = 10;
+
+/// The multiple of the capacity [`WTinyLfuCache::recommended_sample_size`] recommends as the sample size.
+pub const DEFAULT_SAMPLE_SIZE_MULTIPLIER: usize = 10;
+
+const MIN_RECOMMENDED_SAMPLE_SIZE: usize = 10;
+
+/// The reason a key-value pair was evicted, passed to the callback set with
+/// [`WTinyLfuCache::set_on_evict`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EvictReason {
+	/// A window cache victim was admitted into the full main cache by `put`, pushing the main cache victim
+	/// out.
+	Capacity,
+	/// A window cache victim lost the admission contest against the main cache victim during `put`.
+	Rejected,
+	/// The pair didn't fit into the cache anymore after `resize` shrank it.
+	Resize,
+	/// The pair was removed by `clear`.
+	Clear,
+}
+
+/// A segment of the cache a key-value pair can be stored in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Segment {
+	/// The window cache, which every new pair is inserted into.
+	Window,
+	/// The probationary segment of the main cache, which window cache victims are admitted into.
+	Probationary,
+	/// The protected segment of the main cache, which pairs accessed in the probationary segment are
+	/// promoted to.
+	Protected,
+}
+
+/// The outcome of a lookup, passed to the closure given to [`WTinyLfuCache::get_observed`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AccessOutcome {
+	/// The key was found in the cache.
+	Hit {
+		/// The segment the pair was found in.
+		segment: Segment,
+		/// The estimated access frequency of the key after this access.
+		frequency: u16,
+	},
+	/// The key wasn't found in the cache.
+	Miss,
+}
+
+/// A key taking part in an admission decision, returned by [`WTinyLfuCache::admission_candidates`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AdmissionCandidate<'a, K> {
+	/// The key of the pair.
+	pub key: &'a K,
+	/// The estimated access frequency of the key.
+	pub frequency: u16,
+}
+
+/// The standing of a stored key, returned by [`WTinyLfuCache::metadata`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EntryMetadata {
+	/// The estimated access frequency of the key.
+	pub frequency: u16,
+	/// The segment the pair is stored in.
+	pub segment: Segment,
+	/// The recency rank of the pair within its segment, `0` being the most recently used pair.
+	pub lru_rank: usize,
+}
+
+/// What [`WTinyLfuCache::resize_with_policy`] does with the approximation sketch after resizing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ResizeFrequencyPolicy {
+	/// Leaves the sketch untouched, like `resize` does. Fits small adjustments of a stable workload.
+	#[default]
+	Keep,
+	/// Halves the sketch counters, so the learned popularity still counts but adapts faster to the working
+	/// set of the new capacity.
+	Decay,
+	/// Forgets all learned popularity, for resizes that come with a different workload.
+	Reset,
+}
 
 /// W-TinyLFU cache that uses Count Min Sketch as an approximation sketch.
-pub struct WTinyLfuCache<K: Hash + Eq, V> {
-	approximation_sketch: CountMinSketch16<K>,
-	sample_size: usize,
-	sample_counter: usize,
-	doorkeeper: Bloom<K>,
-	window_cache: LruCache<K, V>,
-	main_cache: SlruCache<K, V>,
+///
+/// The cache is `Send` when both `K` and `V` are `Send` and `Sync` when both `K` and `V` are `Sync`, so it
+/// can be shared between threads behind a lock.
+///
+/// Keys are hashed with `S`, which defaults to [`RandomState`]. The approximation sketch and the doorkeeper
+/// use the same hasher with different seeds, so with a deterministic hasher the cache makes the same
+/// admission and eviction decisions on every run. See [`WTinyLfuCache::with_hasher`].
+///
+/// Access frequencies are estimated with `C`, which defaults to a [`CountMinSketch`]. Other
+/// [`FrequencySketch`] implementations can be plugged in with [`WTinyLfuCacheBuilder::build_with_sketch`].
+pub struct WTinyLfuCache<K: Hash + Eq, V, S = RandomState, C = CountMinSketch<K, S>> {
+	cap: usize,
+	frequency: FrequencyModel<K, S, C>,
+	window_cache: WindowCache<K, Slot<V>, S>,
+	main_cache: SlruCache<K, Slot<V>, S>,
+	// Shared by all segments, so every segment hashes a key to the same value.
+	hash_builder: S,
+	// Share of the capacity that goes to the window cache.
+	window_ratio: f64,
+	// Last sequence number stamped on an entry.
+	sequence: u64,
+	stats: Stats,
+	on_evict: Option<Box<OnEvict<K, V>>>,
 }
 
+type OnEvict<K, V> = dyn FnMut(K, V, EvictReason) + Send + Sync;
+
+// The located value returned by a lookup, along with the frequency model of the cache.
+type Lookup<'a, K, V, S, C> = (Option<(Segment, V)>, &'a FrequencyModel<K, S, C>);
+
 impl<K: Hash + Eq, V> WTinyLfuCache<K, V> {
 	/// Creates an W-TinyLFU cache that can hold up to `cap` key-value pairs.
+	///
+	/// The approximation sketch and the doorkeeper allocate memory proportional to `sample_size`, regardless
+	/// of `cap`. Use `try_new` to reject sample sizes that are far larger than the capacity.
 	pub fn new(cap: usize, sample_size: usize) -> Self {
-		let f64_cap: f64 = cap as f64;
-		let window_cache_cap =
-			NonZeroUsize::new(cmp::max(1, (f64_cap * 0.01) as usize)).expect("non zero");
-		let main_cache_cap = cmp::max(1, cap - window_cache_cap.get());
+		Self::with_window_policy(cap, sample_size, WindowPolicy::default())
+	}
 
-		Self {
-			approximation_sketch: CountMinSketch16::new(sample_size * 2, 0.97, 4.0).unwrap(),
-			sample_size,
-			sample_counter: 0,
-			doorkeeper: Bloom::new_for_fp_rate(sample_size, 0.01),
-			window_cache: LruCache::new(window_cache_cap),
-			main_cache: SlruCache::new(main_cache_cap),
-		}
+	/// Returns a sample size for a cache with the specified capacity: [`DEFAULT_SAMPLE_SIZE_MULTIPLIER`] times
+	/// `cap`, but at least 10.
+	///
+	/// The sketch is halved after every sample, so the sample has to be large enough for keys of the working set
+	/// to be accessed several times before their counts decay. Ten accesses per cached pair is a common choice,
+	/// Caffeine uses the same ratio.
+	pub fn recommended_sample_size(cap: usize) -> usize {
+		cmp::max(
+			MIN_RECOMMENDED_SAMPLE_SIZE,
+			cap.saturating_mul(DEFAULT_SAMPLE_SIZE_MULTIPLIER),
+		)
 	}
 
-	/// Inserts a new key-value pair or updates it if a pair with the same key exists, returning the old value.
-	/// Otherwise, returns `None`.
-	pub fn put(&mut self, k: K, v: V) -> Option<V> {
-		if self.window_cache.contains(&k) {
-			return self.window_cache.put(k, v);
-		}
+	/// Creates an W-TinyLFU cache like `new`, but returns an error if `sample_size` is larger than
+	/// [`DEFAULT_MAX_SAMPLE_SIZE_RATIO`] times `cap`.
+	pub fn try_new(cap: usize, sample_size: usize) -> Result<Self, CacheBuildError> {
+		Self::try_with_max_sample_size_ratio(cap, sample_size, DEFAULT_MAX_SAMPLE_SIZE_RATIO)
+	}
 
-		if self.main_cache.contains(&k) {
-			return self.main_cache.put(k, v);
+	/// Creates an W-TinyLFU cache like `new`, but returns an error if `sample_size` is larger than
+	/// `max_ratio` times `cap`.
+	pub fn try_with_max_sample_size_ratio(
+		cap: usize,
+		sample_size: usize,
+		max_ratio: usize,
+	) -> Result<Self, CacheBuildError> {
+		if sample_size > cap.saturating_mul(max_ratio) {
+			return Err(CacheBuildError::SampleSizeTooLarge { cap, sample_size });
 		}
 
-		self.push(k, v);
-		None
+		Ok(Self::new(cap, sample_size))
 	}
 
-	/// Inserts a new key-value pair or updates it if a pair with the same key exists, returning the the old pair.
-	/// Returns the evicted key-value pair if there is one.
-	/// Otherwise, returns `None`.
-	pub fn push(&mut self, k: K, v: V) -> Option<(K, V)> {
-		if self.window_cache.contains(&k) {
-			return self.window_cache.push(k, v);
+	/// Creates an W-TinyLFU cache that can hold up to `cap` key-value pairs and uses the specified eviction
+	/// policy for the window cache. See [`WindowPolicy`] for how the policies differ.
+	pub fn with_window_policy(cap: usize, sample_size: usize, window_policy: WindowPolicy) -> Self {
+		WTinyLfuCacheBuilder::new()
+			.capacity(cap)
+			.sample_size(sample_size)
+			.window_policy(window_policy)
+			.build()
+			.expect("default window ratio is valid")
+	}
+
+	/// Creates an W-TinyLFU cache for fixed-size entries that fits into roughly `bytes` bytes.
+	///
+	/// The memory allocated by the approximation sketch, the doorkeeper and the filter of reused keys for
+	/// `sample_size` is subtracted from the budget first, and the rest is divided by `entry_size`.
+	/// `entry_size` should include the per-entry bookkeeping of the cache, not just the size of the key and
+	/// the value. The cache can hold at least one pair even if the budget is smaller than the overhead.
+	///
+	/// # Panics
+	///
+	/// Panics if `entry_size` is `0`.
+	pub fn with_memory_budget(bytes: usize, entry_size: usize, sample_size: usize) -> Self {
+		assert!(entry_size > 0, "entry size must be at least 1 byte");
+		let overhead = FrequencyModel::<K>::memory_overhead(sample_size);
+		let cap = cmp::max(1, bytes.saturating_sub(overhead) / entry_size);
+
+		Self::new(cap, sample_size)
+	}
+
+	/// Creates an W-TinyLFU cache like `new` and inserts the entries with `put`, in iteration order.
+	///
+	/// Entries go through the usual admission policy, so when there are more entries than the cache can hold,
+	/// later entries evict or lose against earlier ones. Later entries with a duplicate key replace the value.
+	pub fn with_entries<I: IntoIterator<Item = (K, V)>>(
+		cap: usize,
+		sample_size: usize,
+		entries: I,
+	) -> Self {
+		let mut cache = Self::new(cap, sample_size);
+		for (k, v) in entries {
+			cache.put(k, v);
 		}
 
-		if self.main_cache.contains(&k) {
-			return self.main_cache.push(k, v);
+		cache
+	}
+
+	/// Creates an W-TinyLFU cache like `new` and restores the pairs along with their access frequencies, e.g.
+	/// the estimates persisted with a previous instance, so admission decisions account for them right away.
+	///
+	/// Every key's frequency is recorded in the approximation sketch before its pair is inserted, like
+	/// `observe_weighted` would. Frequencies are capped at half the counter range so a single key can't
+	/// trigger a reset, but recording more accesses than `sample_size` in total still ends the sample and
+	/// halves the counts. Pairs skip the admission policy until the cache is full, later ones are inserted
+	/// like `put` does and compete with the restored pairs.
+	pub fn warm_start<I: IntoIterator<Item = (K, V, u16)>>(
+		cap: usize,
+		sample_size: usize,
+		items: I,
+	) -> Self {
+		let mut cache = Self::new(cap, sample_size);
+		for (k, v, frequency) in items {
+			cache.frequency.seed(&k, frequency);
+			if cache.len() < cache.cap {
+				cache.fill(k, v);
+			} else {
+				cache.put(k, v);
+			}
 		}
 
-		match self.window_cache.push(k, v) {
-			Some((window_cache_victim_k, window_cache_victim_v)) => {
-				match self.main_cache.peek_lru_if_full() {
-					Some((main_cache_victim_k, _)) => {
-						let window_cache_victim_estimation = self.estimate(&window_cache_victim_k);
-						let main_cache_victim_estimation = self.estimate(main_cache_victim_k);
+		cache
+	}
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Clone> WTinyLfuCache<K, V, S> {
+	/// Creates an W-TinyLFU cache like `new`, but hashes keys with `hash_builder` instead of a randomly seeded
+	/// [`RandomState`]. This allows faster hashers for keys that are cheap to compare, and deterministic
+	/// hashers for reproducible behavior, since the approximation sketch uses the same hasher.
+	pub fn with_hasher(cap: usize, sample_size: usize, hash_builder: S) -> Self {
+		WTinyLfuCacheBuilder::new()
+			.capacity(cap)
+			.sample_size(sample_size)
+			.build_with_hasher(hash_builder)
+			.expect("default window ratio is valid")
+	}
+
+	/// Returns the `(width, depth)` dimensions of the count-min sketch used as the approximation sketch.
+	///
+	/// The sketch is sized for `sample_size * 2` keys, so its width grows roughly linearly with `sample_size`
+	/// (rounded up to a power of two) while its depth is fixed.
+	pub fn sketch_dimensions(&self) -> (usize, usize) {
+		self.frequency.sketch_dimensions()
+	}
+}
 
-						if window_cache_victim_estimation > main_cache_victim_estimation {
-							return self
-								.main_cache
-								.push(window_cache_victim_k, window_cache_victim_v);
-						}
+impl<K: Hash + Eq, V, S: BuildHasher + Clone, C: FrequencySketch<K, S>> WTinyLfuCache<K, V, S, C> {
+	// Creates the cache from a configuration validated by the builder.
+	pub(crate) fn from_builder(
+		cap: usize,
+		sample_size: usize,
+		window_policy: WindowPolicy,
+		window_ratio: f64,
+		probationary_ratio: f64,
+		decay_strategy: DecayStrategy,
+		hash_builder: S,
+	) -> Self {
+		let (window_cache_cap, main_cache_cap) = segment_caps(cap, window_ratio);
+		let mut frequency = FrequencyModel::new(sample_size, hash_builder.clone());
+		frequency.set_decay_strategy(decay_strategy);
 
-						Some((window_cache_victim_k, window_cache_victim_v))
-					}
-					None => self
-						.main_cache
-						.push(window_cache_victim_k, window_cache_victim_v),
-				}
-			}
-			None => None,
+		Self {
+			cap,
+			frequency,
+			window_cache: WindowCache::new(window_policy, window_cache_cap, hash_builder.clone()),
+			main_cache: SlruCache::new(main_cache_cap, probationary_ratio, hash_builder.clone()),
+			hash_builder,
+			window_ratio,
+			sequence: 0,
+			stats: Stats::default(),
+			on_evict: None,
 		}
 	}
 
-	/// Retrieves a value for the specified key from the cache and returns an immutable reference if it exists.
-	/// If such key-value pair exists, its count in the approximation sketch is incremented.
+	/// Inserts a new key-value pair or updates it if a pair with the same key exists, returning the old value.
 	/// Otherwise, returns `None`.
-	pub fn get(&mut self, k: &K) -> Option<&V> {
-		let v = match self.window_cache.get(k) {
-			Some(v) => Some(v),
-			None => self.main_cache.get(k),
-		};
+	///
+	/// An update counts as an access to the key in the approximation sketch like `get` does, so keys that are
+	/// written often are as likely to stay cached as keys that are read often. Inserting a new key doesn't.
+	#[inline]
+	pub fn put(&mut self, k: K, v: V) -> Option<V> {
+		self.put_located(k, v).0
+	}
 
-		if v.is_some() {
-			if self.doorkeeper.check(k) {
-				self.approximation_sketch.increment(k);
-				self.sample_counter += 1;
-
-				if self.sample_counter >= self.sample_size {
-					self.approximation_sketch.reset();
-					self.doorkeeper.clear();
-					self.sample_counter = 0;
-				}
-			} else {
-				self.doorkeeper.set(k);
+	/// Same as `put`, but also returns the segment the pair is stored in after the call, without looking the
+	/// key up again.
+	///
+	/// An update leaves the pair in the segment it was already stored in, since `put` doesn't promote pairs.
+	/// A new pair is always inserted into the window cache, so a fresh insert returns `Segment::Window`.
+	pub fn put_located(&mut self, k: K, v: V) -> (Option<V>, Segment) {
+		let entry = Slot::new(v, &mut self.sequence);
+		let entry = match self.replace_entry(&k, entry) {
+			Ok((segment, old_entry)) => {
+				self.frequency.increment(&k);
+				return (Some(old_entry.v), segment);
 			}
+			Err(entry) => entry,
+		};
+
+		if let Some((k, entry, reason)) = self.insert(k, entry) {
+			self.evict(k, entry.v, reason);
 		}
+		self.debug_assert_capacity_invariant();
 
-		v
+		(None, Segment::Window)
 	}
 
-	/// Retrieves a value for the specified key from the cache and returns a mutable reference if it exists.
-	/// If such key-value pair exists, its count in the approximation sketch is incremented.
-	/// Otherwise, returns `None`.
-	pub fn get_mut(&mut self, k: &K) -> Option<&mut V> {
-		let v = match self.window_cache.get_mut(k) {
-			Some(v) => Some(v),
-			None => self.main_cache.get_mut(k),
-		};
+	/// Moves the pairs of `other` into the cache, calling `resolve` with the key, the stored value and the
+	/// value from `other` for every key stored in both. The value `resolve` returns replaces the stored one.
+	///
+	/// Pairs of `other` are taken in the order its `into_iter` yields them. Keys that aren't stored in the cache
+	/// are inserted like `put` does, through the usual admission policy, so they can evict stored pairs or be
+	/// rejected. A resolved pair stays in its segment and moves to the front of it like an update with `put`
+	/// does, a pair in a FIFO or SIEVE window is reinserted as the newest one. If `resolve` panics, the pair it
+	/// was called for is lost. The frequency model of `other` is dropped.
+	pub fn merge_with<F: FnMut(&K, V, V) -> V>(
+		&mut self,
+		other: WTinyLfuCache<K, V, S, C>,
+		mut resolve: F,
+	) {
+		for (k, incoming) in other {
+			if !self.contains(&k) {
+				self.put(k, incoming);
+				continue;
+			}
 
-		if v.is_some() {
-			if self.doorkeeper.check(k) {
-				self.approximation_sketch.increment(k);
-				self.sample_counter += 1;
-
-				if self.sample_counter >= self.sample_size {
-					self.approximation_sketch.reset();
-					self.doorkeeper.clear();
-					self.sample_counter = 0;
-				}
-			} else {
-				self.doorkeeper.set(k);
+			let sequence = &mut self.sequence;
+			let merge = |entry: Slot<V>| {
+				let mut merged = Slot::new(resolve(&k, entry.v, incoming), sequence);
+				merged.dirty = entry.dirty;
+				merged
+			};
+			match self.window_cache.contains(&k) {
+				true => self.window_cache.update_with(&k, merge),
+				false => self.main_cache.update_with(&k, merge),
 			}
 		}
-
-		v
+		self.debug_assert_capacity_invariant();
 	}
 
-	/// Retrieves a value for the specified key from the cache and returns an immutable reference if it exists.
-	/// Does not increment pair's count in the approximation sketch.
-	/// If the pair doesn't exist, returns `None`.
-	pub fn peek(&self, k: &K) -> Option<&V> {
-		match self.window_cache.peek(k) {
-			Some(v) => Some(v),
-			None => self.main_cache.peek(k),
+	/// Inserts a new key-value pair or updates it if a pair with the same key exists, passing the old value to
+	/// `on_replace` instead of returning it.
+	/// `on_replace` isn't called if the key wasn't already stored in the cache.
+	pub fn put_with<F: FnOnce(V)>(&mut self, k: K, v: V, on_replace: F) {
+		if let Some(old_v) = self.put(k, v) {
+			on_replace(old_v);
 		}
 	}
 
-	/// Retrieves a value for the specified key from the cache and returns a mutable reference if it exists.
-	/// Does not increment pair's count in the approximation sketch.
-	/// If the pair doesn't exist, returns `None`.
-	pub fn peek_mut(&mut self, k: &K) -> Option<&mut V> {
-		match self.window_cache.peek_mut(k) {
-			Some(v) => Some(v),
-			None => self.main_cache.peek_mut(k),
+	/// Looks the key up like `get_mut` does and returns an [`Entry`] for in-place manipulation, which inserts
+	/// like `put` does if the key isn't stored in the cache.
+	///
+	/// The access is recorded right away, so a stored key is promoted and counted in the approximation sketch
+	/// once, no matter how the entry is used afterwards.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use wtinylfu::WTinyLfuCache;
+	///
+	/// let mut cache = WTinyLfuCache::new(10, 10);
+	/// for word in ["a", "b", "a"] {
+	///     *cache.entry(word).or_insert(0) += 1;
+	/// }
+	///
+	/// assert_eq!(cache.peek("a"), Some(&2));
+	/// ```
+	pub fn entry(&mut self, k: K) -> Entry<'_, K, V, S, C> {
+		match self.lookup_mut(&k).0.is_some() {
+			true => Entry::Occupied(OccupiedEntry::new(self, k)),
+			false => Entry::Vacant(VacantEntry::new(self, k)),
 		}
 	}
 
-	/// Returns a reference to the least recently used key-value pair from the window cache.
-	/// Returns `None` if the cache is empty.
+	/// Retrieves a reference to the value of the key like `get` does, or inserts the value returned by `f` like
+	/// `put` does if the key isn't stored in the cache. See `get_or_insert_with_mut`.
 	#[inline]
-	pub fn peek_lru_window(&self) -> Option<(&K, &V)> {
-		self.window_cache.peek_lru()
+	pub fn get_or_insert_with<F: FnOnce() -> V>(&mut self, k: K, f: F) -> &V {
+		self.get_or_insert_with_mut(k, f)
 	}
 
-	/// Returns a reference to the least recently used key-value pair from the main cache.
-	/// Returns `None` if the cache is empty.
-	#[inline]
-	pub fn peek_lru_main(&self) -> Option<(&K, &V)> {
-		self.main_cache.peek_lru()
+	/// Retrieves a mutable reference to the value of the key like `get_mut` does, or inserts the value returned
+	/// by `f` like `put` does if the key isn't stored in the cache. The pair evicted to make room is passed to
+	/// the eviction callback, use `get_or_insert_with_evicting` to get it back instead.
+	///
+	/// `f` is only called on a miss, at most once. If `f` panics the cache is left as it was.
+	pub fn get_or_insert_with_mut<F: FnOnce() -> V>(&mut self, k: K, f: F) -> &mut V {
+		if self.contains(&k) {
+			let v = self.lookup_mut(&k).0.map(|(_, v)| v);
+			return v.expect("key is stored");
+		}
+
+		let v = f();
+		self.stats.record_lookup(None);
+		self.insert_missing(k, v)
 	}
 
-	/// Returns a bool indicating whether a key-value pair stored in the cache.
-	pub fn contains(&self, k: &K) -> bool {
-		match self.window_cache.contains(k) {
-			true => true,
-			false => self.main_cache.contains(k),
+	/// Retrieves a reference to the value of the key like `get` does, or inserts the value returned by `f` like
+	/// `put` does if the key isn't stored in the cache and `f` succeeds.
+	///
+	/// `f` is only called on a miss, at most once. If it returns an error, the error is returned unchanged and
+	/// the cache is left as it was: nothing is inserted, the approximation sketch and the doorkeeper aren't
+	/// touched and the miss isn't counted in `stats`.
+	pub fn get_or_try_insert_with<F, E>(&mut self, k: K, f: F) -> Result<&V, E>
+	where
+		F: FnOnce() -> Result<V, E>,
+	{
+		if self.contains(&k) {
+			let v = self.lookup(&k).0.map(|(_, v)| v);
+			return Ok(v.expect("key is stored"));
 		}
+
+		let v = f()?;
+		self.stats.record_lookup(None);
+		Ok(self.insert_missing(k, v))
 	}
 
-	/// Removes a key-value pair with the specified key and returns pair's value.
-	pub fn pop(&mut self, k: &K) -> Option<V> {
-		match self.window_cache.pop(k) {
-			Some(v) => Some(v),
-			None => self.main_cache.pop(k),
+	/// Retrieves a mutable reference to the value of the key like `get_mut` does, or inserts the value returned
+	/// by `f` like `put` does if the key isn't stored in the cache and `sizer` measures it at most `max_size`.
+	/// This keeps a single huge value from evicting a large part of the cache.
+	///
+	/// `f` is only called on a miss, at most once. An oversized value is returned as the error and the cache is
+	/// left as it was, like with an error of `get_or_try_insert_with`. On a hit the stored value is returned
+	/// without measuring it again, even if it's larger than `max_size`.
+	pub fn get_or_insert_with_limit<F: FnOnce() -> V>(
+		&mut self,
+		k: K,
+		f: F,
+		max_size: usize,
+		sizer: impl Fn(&V) -> usize,
+	) -> Result<&mut V, V> {
+		if self.contains(&k) {
+			let v = self.lookup_mut(&k).0.map(|(_, v)| v);
+			return Ok(v.expect("key is stored"));
 		}
-	}
 
-	/// Removes a key-value pair with the specified key and returns the pair.
-	pub fn pop_entry(&mut self, k: &K) -> Option<(K, V)> {
-		match self.window_cache.pop_entry(k) {
-			Some(v) => Some(v),
-			None => self.main_cache.pop_entry(k),
+		let v = f();
+		if sizer(&v) > max_size {
+			return Err(v);
 		}
-	}
 
-	/// Removes the least recently used key-value pair from the window cache and returns the pair.
-	pub fn pop_lru_window(&mut self) -> Option<(K, V)> {
-		self.window_cache.pop_lru()
+		self.stats.record_lookup(None);
+		Ok(self.insert_missing(k, v))
 	}
 
-	/// Removes the least recently used key-value pair from the main cache and returns the pair.
-	pub fn pop_lru_main(&mut self) -> Option<(K, V)> {
-		self.main_cache.pop_lru()
-	}
+	// Inserts a key that isn't stored in the cache like `put` does. Returns a reference to the inserted value.
+	fn insert_missing(&mut self, k: K, v: V) -> &mut V {
+		let entry = Slot::new(v, &mut self.sequence);
+		if let Some((k, entry, reason)) = self.insert(k, entry) {
+			self.evict(k, entry.v, reason);
+		}
+		self.debug_assert_capacity_invariant();
 
-	/// Returns the number of stored key-value pairs.
-	pub fn len(&self) -> usize {
-		self.window_cache.len() + self.main_cache.len()
+		// New pairs are always inserted at the front of the window cache, only older pairs are pushed out of it.
+		let entry = self
+			.window_cache
+			.peek_newest_mut()
+			.expect("pair was just inserted");
+		&mut entry.v
 	}
 
-	/// Returns a bool indicating whether the cache is empty.
-	pub fn is_empty(&self) -> bool {
-		self.len() == 0
-	}
+	/// Retrieves a mutable reference to the value of the key like `get_mut` does, or inserts the value returned
+	/// by `f` if the key isn't stored in the cache. Also returns the pair that was evicted to make room for the
+	/// new one, so it can be flushed in the same call.
+	///
+	/// On a hit, nothing is evicted and the returned pair is always `None`. The evicted pair is returned to the
+	/// caller instead of being passed to the eviction callback.
+	///
+	/// `f` is only called on a miss, at most once. It can't access the cache, since the cache is mutably
+	/// borrowed for the whole call. The cache isn't modified before `f` returns, so if `f` panics the cache is
+	/// left as it was and the miss isn't counted.
+	pub fn get_or_insert_with_evicting<F: FnOnce() -> V>(
+		&mut self,
+		k: K,
+		f: F,
+	) -> (&mut V, Option<(K, V)>) {
+		if self.contains(&k) {
+			let v = self.lookup_mut(&k).0.map(|(_, v)| v);
+			return (v.expect("key is stored"), None);
+		}
 
-	/// Returns the capacity of the cache (the maximum number of key-value pairs that the cache can store).
-	pub fn cap(&self) -> usize {
-		self.window_cache.cap().get() + self.main_cache.cap()
+		let v = f();
+		self.stats.record_lookup(None);
+		let entry = Slot::new(v, &mut self.sequence);
+		let evicted = self.insert(k, entry).map(|(k, entry, _)| (k, entry.v));
+
+		// New pairs are always inserted at the front of the window cache.
+		let entry = self
+			.window_cache
+			.peek_newest_mut()
+			.expect("pair was just inserted");
+		(&mut entry.v, evicted)
 	}
 
-	/// Resizes the cache. If the new capacity is smaller than the size of the current cache any entries past the new capacity are discarded.
-	pub fn resize(&mut self, cap: usize) {
-		let f64_cap: f64 = cap as f64;
-		let window_cache_cap =
-			NonZeroUsize::new(cmp::max(1, (f64_cap * 0.01) as usize)).expect("non zero size");
-		let main_cache_cap = cmp::max(1, cap - window_cache_cap.get());
+	/// Updates the value of an existing key-value pair and returns the old value, treating the call as a
+	/// write like an update with `put` does: the pair moves to the front of its segment without being promoted
+	/// and the access is counted in the approximation sketch, but not in `stats`. If the key isn't stored in
+	/// the cache, nothing is inserted and `None` is returned.
+	pub fn put_if_present<Q>(&mut self, k: &Q, v: V) -> Option<V>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		let entry = match self.window_cache.get_mut(k) {
+			Some(entry) => entry,
+			None => self.main_cache.get_mut_without_promotion(k)?.1,
+		};
 
-		self.window_cache.resize(window_cache_cap);
-		self.main_cache.resize(main_cache_cap);
+		entry.touch(&mut self.sequence);
+		let old_v = mem::replace(&mut entry.v, v);
+		self.frequency.increment(k);
+
+		Some(old_v)
 	}
 
-	/// Removes all key-value pairs from the cache.
-	pub fn clear(&mut self) {
-		self.window_cache.clear();
-		self.main_cache.clear();
+	/// Swaps in a new value for an existing key and returns the old one, treating the call as a read like
+	/// `get_mut` does: the key's count in the approximation sketch is incremented, the lookup is counted in
+	/// `stats` and a probationary pair is promoted. If the key isn't stored in the cache, nothing is inserted
+	/// and `None` is returned.
+	///
+	/// Use `put_if_present` instead to treat the update as a write, which counts in the sketch as well but
+	/// neither counts in `stats` nor promotes the pair.
+	pub fn replace<Q>(&mut self, k: &Q, v: V) -> Option<V>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		self.get_mut(k).map(|stored_v| mem::replace(stored_v, v))
 	}
 
-	#[inline]
-	fn estimate(&self, k: &K) -> u16 {
-		let mut estimate = self.approximation_sketch.estimate(k);
-		if self.doorkeeper.check(k) {
-			estimate += 1;
-		}
+	/// Inserts a new key-value pair or updates it if a pair with the same key exists, returning the the old pair.
+	/// Returns the evicted key-value pair if there is one.
+	/// Otherwise, returns `None`.
+	///
+	/// Like with `put`, an update counts as an access to the key in the approximation sketch.
+	pub fn push(&mut self, k: K, v: V) -> Option<(K, V)> {
+		let entry = Slot::new(v, &mut self.sequence);
+		let evicted = match self.replace_entry(&k, entry) {
+			Ok((_, old_entry)) => {
+				self.frequency.increment(&k);
+				Some((k, old_entry))
+			}
+			Err(entry) => self.insert(k, entry).map(|(k, entry, _)| (k, entry)),
+		};
+		self.debug_assert_capacity_invariant();
 
-		estimate
+		evicted.map(|(k, entry)| (k, entry.v))
 	}
 
-	/// An iterator visiting all entries in roughly most-recently used order.
-	///
-	/// # Examples
-	///
-	/// ```
-	/// use wtinylfu::WTinyLfuCache;
+	/// Sets a callback that is called with every key-value pair the cache evicts on its own, along with the
+	/// reason it was evicted. Replaces the previously set callback.
 	///
-	/// let mut cache = WTinyLfuCache::new(3, 10);
-	/// cache.put("a", 1);
-	/// cache.put("b", 2);
-	/// cache.put("c", 3);
+	/// Pairs that are returned to the caller, like the ones evicted by `push` or removed by `pop`, aren't
+	/// passed to the callback. The callback has to be `Send` and `Sync` so the cache stays `Send` and `Sync`.
+	pub fn set_on_evict<F>(&mut self, on_evict: F)
+	where
+		F: FnMut(K, V, EvictReason) + Send + Sync + 'static,
+	{
+		self.on_evict = Some(Box::new(on_evict));
+	}
+
+	/// Sets a listener that is called with every key-value pair that is dropped from the cache, like the
+	/// callback set with `set_on_evict` but without the reason, and returns the cache. Useful to flush dirty
+	/// values when they leave the cache.
 	///
-	/// for (key, val) in cache.iter() {
-	///     println!("key: {} val: {}", key, val);
-	/// }
-	/// ```
-	pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
-		self.window_cache.iter().chain(self.main_cache.iter())
+	/// The listener is called exactly once for each pair that loses the admission contest, is pushed out of the
+	/// main cache, doesn't fit after `resize` or is removed by `clear` or a dropped `drain` iterator. Updates
+	/// of stored keys and moves between segments don't call it, and neither do pairs returned to the caller,
+	/// like the ones evicted by `push`.
+	pub fn with_eviction_listener<F>(mut self, mut listener: F) -> Self
+	where
+		F: FnMut(K, V) + Send + Sync + 'static,
+	{
+		self.set_on_evict(move |k, v, _| listener(k, v));
+		self
 	}
-}
 
-#[cfg(test)]
-mod tests {
-	use super::WTinyLfuCache;
-	use std::hash::Hash;
+	// Replaces the entry of a stored key and moves it to the front of its segment, without promoting it.
+	// Returns the segment of the key and the old entry, or gives the new one back if the key isn't stored. The
+	// key is hashed once per segment probed, since the lookup and the update are done at once.
+	fn replace_entry(&mut self, k: &K, entry: Slot<V>) -> Result<(Segment, Slot<V>), Slot<V>> {
+		let (segment, stored_entry) = match self.window_cache.get_mut(k) {
+			Some(stored_entry) => (Segment::Window, stored_entry),
+			None => match self.main_cache.get_mut_without_promotion(k) {
+				Some(stored) => stored,
+				None => return Err(entry),
+			},
+		};
 
-	fn iter_keys<K: Hash + Eq + Ord + Copy, V>(cache: &WTinyLfuCache<K, V>) -> Vec<K> {
-		let mut out = cache.iter().map(|(k, _)| *k).collect::<Vec<_>>();
-		out.sort();
-		out
+		// The pair keeps its mark from `get_mut_dirty` until it is taken.
+		let mut entry = entry;
+		entry.dirty = stored_entry.dirty;
+		Ok((segment, mem::replace(stored_entry, entry)))
 	}
 
-	#[test]
-	fn store_and_retrieve_items() {
-		let mut cache = WTinyLfuCache::new(2, 10);
-		cache.push(1, "one");
-		cache.push(2, "two");
-		assert_eq!(cache.get(&1), Some(&"one"));
-		assert_eq!(cache.get(&2), Some(&"two"));
-		assert_eq!(&iter_keys(&cache), &[1, 2]);
+	// Inserts a key that isn't stored in the cache yet into the window cache, offering the window cache victim
+	// to the main cache. Returns the pair that was evicted from the cache along with the reason.
+	fn insert(&mut self, k: K, entry: Slot<V>) -> Option<(K, Slot<V>, EvictReason)> {
+		let (window_cache_victim_k, window_cache_victim_entry) =
+			self.window_cache.push_new(k, entry)?;
+		self.admit(window_cache_victim_k, window_cache_victim_entry)
 	}
 
-	#[test]
-	fn store_retrieve_and_pop_items() {
-		let mut cache = WTinyLfuCache::new(2, 10);
-		cache.push(1, "one");
-		cache.push(2, "two");
-		assert_eq!(cache.get(&1), Some(&"one"));
-		assert_eq!(cache.get(&2), Some(&"two"));
+	// Inserts a pair like `put`, but moves window cache victims into the main cache without an admission
+	// decision. Only evicts if the cache is already full.
+	fn fill(&mut self, k: K, v: V) {
+		let entry = Slot::new(v, &mut self.sequence);
+		let entry = match self.replace_entry(&k, entry) {
+			Ok(_) => return,
+			Err(entry) => entry,
+		};
 
-		cache.pop(&1);
-		assert_eq!(cache.get(&1), None);
-		assert_eq!(cache.get(&2), Some(&"two"));
-		assert_eq!(&iter_keys(&cache), &[2]);
+		if let Some((k, entry)) = self.window_cache.push_new(k, entry) {
+			if let Some((k, entry)) = self.main_cache.fill(k, entry) {
+				self.evict(k, entry.v, EvictReason::Capacity);
+			}
+		}
+		self.debug_assert_capacity_invariant();
 	}
 
-	#[test]
-	fn check_if_lru_is_correct() {
-		let mut cache = WTinyLfuCache::new(500, 10);
-		cache.push(1, "one");
-		cache.push(2, "two");
-		cache.push(3, "three");
-		cache.push(4, "four");
-		cache.push(5, "five");
-		assert_eq!(cache.peek_lru_window(), Some((&1, &"one")));
-		assert_eq!(cache.peek_lru_main(), None);
+	// Offers a window cache victim to the main cache. If the main cache is full, the victim is only admitted
+	// if it's estimated to be accessed more frequently than the main cache victim. Returns the pair that was
+	// evicted from the cache, if there is one.
+	fn admit(&mut self, k: K, entry: Slot<V>) -> Option<(K, Slot<V>, EvictReason)> {
+		match self.main_cache.peek_lru_if_full() {
+			Some((main_cache_victim_k, _)) => {
+				let window_cache_victim_estimation = self.estimate_frequency(&k);
+				let main_cache_victim_estimation = self.estimate_frequency(main_cache_victim_k);
 
-		cache.get(&1);
-		cache.get(&2);
-		cache.get(&3);
-		cache.get(&4);
-		cache.get(&5);
-		assert_eq!(cache.peek_lru_window(), Some((&1, &"one")));
-		assert_eq!(cache.peek_lru_main(), None);
+				if window_cache_victim_estimation > main_cache_victim_estimation {
+					return self.admit_unchecked(k, entry);
+				}
 
-		cache.get(&3);
-		cache.get(&2);
-		cache.get(&4);
-		cache.get(&1);
-		cache.get(&5);
-		assert_eq!(cache.peek_lru_window(), Some((&3, &"three")));
-		assert_eq!(cache.peek_lru_main(), None);
+				self.stats.record_rejection();
+				Some((k, entry, EvictReason::Rejected))
+			}
+			None => self.admit_unchecked(k, entry),
+		}
 	}
 
-	#[test]
-	fn check_if_cap_and_len_are_correct() {
-		let mut cache = WTinyLfuCache::new(3, 10);
-		cache.push(1, "one");
-		cache.push(2, "two");
-		assert_eq!(cache.cap(), 3);
-		assert_eq!(cache.len(), 2);
-
-		cache.get(&1);
-		cache.get(&2);
-		assert_eq!(cache.cap(), 3);
-		assert_eq!(cache.len(), 2);
-
-		cache.push(3, "three");
-		assert_eq!(cache.cap(), 3);
-		assert_eq!(cache.len(), 3);
+	// Moves a window cache victim into the main cache, evicting the main cache victim if it's full.
+	fn admit_unchecked(&mut self, k: K, entry: Slot<V>) -> Option<(K, Slot<V>, EvictReason)> {
+		let evicted = self.main_cache.push_new(k, entry);
+		self.stats.record_admission(evicted.is_some());
+		evicted.map(|(k, entry)| (k, entry, EvictReason::Capacity))
+	}
 
-		cache.get(&3);
-		assert_eq!(cache.cap(), 3);
-		assert_eq!(cache.len(), 3);
+	// Checks that no segment holds more pairs than its capacity and that the cache doesn't hold more pairs than
+	// the segments can hold together. That sum can exceed `cap` for tiny caches, see `cap`. Only runs in debug
+	// builds. The crate's own tests also check that `len` matches the number of stored pairs, which takes time
+	// proportional to `len` and would make filling a cache quadratic in every other debug build.
+	#[inline]
+	fn debug_assert_capacity_invariant(&self) {
+		debug_assert!(self.window_cache.len() <= self.window_cache.cap().get());
+		self.main_cache.debug_assert_capacity_invariant();
+		debug_assert!(self.len() <= self.window_cache.cap().get() + self.main_cache.cap());
+		#[cfg(test)]
+		debug_assert!(self.verify_len());
+	}
 
-		assert_eq!(&iter_keys(&cache), &[1, 2, 3]);
+	#[inline]
+	fn evict(&mut self, k: K, v: V, reason: EvictReason) {
+		if let Some(on_evict) = self.on_evict.as_mut() {
+			on_evict(k, v, reason);
+		}
+	}
+
+	/// Retrieves a value for the specified key from the cache and returns an immutable reference if it exists.
+	/// If such key-value pair exists, its count in the approximation sketch is incremented.
+	/// Otherwise, returns `None`.
+	///
+	/// Doesn't allocate, except when a pair is promoted out of the probationary segment, which moves it to a
+	/// newly allocated node of the protected segment.
+	///
+	/// The key may be any borrowed form of the cache's key type, like `&str` for `String` keys. It's hashed
+	/// the same way for lookups and for the approximation sketch, so both forms count as the same key.
+	pub fn get<Q>(&mut self, k: &Q) -> Option<&V>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		self.lookup(k).0.map(|(_, v)| v)
+	}
+
+	/// Works like `get`, but a hit always increments the key's count in the approximation sketch, even if it's
+	/// the key's first access in the current sample. A regular `get` only sets the key in the doorkeeper then.
+	/// Useful for accesses that are known to matter, like warming the cache with important keys.
+	pub fn get_counted<Q>(&mut self, k: &Q) -> Option<&V>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		let (v, _) = self.lookup_mut_recording(k, FrequencyModel::increment_counted);
+		v.map(|(_, v)| &*v)
+	}
+
+	/// Records an access to a stored key exactly like `get` does, updating its recency, its count in the
+	/// approximation sketch and the hit counter, without returning the value. Returns `true` if the key is
+	/// stored. Otherwise, returns `false` and changes nothing, unlike `get`, which counts a miss.
+	pub fn touch<Q>(&mut self, k: &Q) -> bool
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		if !self.contains(k) {
+			return false;
+		}
+
+		self.lookup(k).0.is_some()
+	}
+
+	/// Retrieves a value for the specified key from the cache and returns a mutable reference if it exists.
+	/// If such key-value pair exists, its count in the approximation sketch is incremented.
+	/// Otherwise, returns `None`.
+	pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		self.lookup_mut(k).0.map(|(_, v)| v)
+	}
+
+	/// Works like `get_mut`, but also marks the pair as dirty, assuming the caller is about to change the value.
+	/// `take_dirty` returns the keys of all marked pairs, so a write-back layer can persist them later.
+	///
+	/// The pair is marked on every hit, whether the value is actually changed or not. The mark survives
+	/// updates with `put` and is dropped along with the pair when it leaves the cache, so pairs that are
+	/// evicted have to be persisted from the eviction callback.
+	pub fn get_mut_dirty<Q>(&mut self, k: &Q) -> Option<&mut V>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		let (entry, _) = self.lookup_slot_recording(k, FrequencyModel::increment);
+		entry.map(|(_, entry)| {
+			entry.dirty = true;
+			&mut entry.v
+		})
+	}
+
+	/// Returns the keys of all pairs marked by `get_mut_dirty` and clears their marks. The keys are returned in
+	/// no particular order.
+	pub fn take_dirty(&mut self) -> Vec<K>
+	where
+		K: Clone,
+	{
+		self.window_cache
+			.iter_mut()
+			.chain(self.main_cache.iter_mut())
+			.filter_map(|(k, entry)| mem::take(&mut entry.dirty).then(|| k.clone()))
+			.collect()
+	}
+
+	/// Retrieves mutable references to the values of several distinct keys at once. Every key is looked up like
+	/// `get_mut` does, so stored keys are promoted and counted in the approximation sketch. Keys that aren't
+	/// stored get `None`.
+	///
+	/// The keys may be stored in the same segment. The references are collected in a single pass over the
+	/// cache after all lookups are done, which takes time proportional to `len` times `N`.
+	///
+	/// # Panics
+	///
+	/// Panics if any two keys are equal.
+	pub fn get_disjoint_mut<Q, const N: usize>(&mut self, ks: [&Q; N]) -> [Option<&mut V>; N]
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		for (i, k) in ks.iter().enumerate() {
+			assert!(
+				!ks[..i].contains(k),
+				"keys passed to get_disjoint_mut must be distinct"
+			);
+		}
+
+		let mut found = 0;
+		for k in ks {
+			if self.lookup_mut(k).0.is_some() {
+				found += 1;
+			}
+		}
+
+		let mut values: [Option<&mut V>; N] = std::array::from_fn(|_| None);
+		for (k, v) in self.iter_mut() {
+			if found == 0 {
+				break;
+			}
+			if let Some(i) = ks.iter().position(|key| k.borrow() == *key) {
+				values[i] = Some(v);
+				found -= 1;
+			}
+		}
+
+		values
+	}
+
+	/// Looks the key up in the window cache only and returns a mutable reference to its value, updating its
+	/// recency and its count in the approximation sketch like `get` does. The main cache is ignored entirely,
+	/// so `None` is returned for keys that are only stored there. The lookup isn't counted in `stats`.
+	pub fn get_window<Q>(&mut self, k: &Q) -> Option<&mut V>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		let entry = self.window_cache.get_mut(k)?;
+		entry.touch(&mut self.sequence);
+		self.frequency.increment(k);
+
+		Some(&mut entry.v)
+	}
+
+	/// Retrieves cloned values for each of the keys, in the same order as the keys, with `None` for keys that
+	/// aren't stored in the cache. Hits are counted in the approximation sketch like `get` does.
+	pub fn get_cloned_all<'a, I, Q>(&mut self, keys: I) -> Vec<Option<V>>
+	where
+		I: IntoIterator<Item = &'a Q>,
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized + 'a,
+		V: Clone,
+	{
+		keys.into_iter().map(|k| self.get(k).cloned()).collect()
+	}
+
+	/// Works like `get`, but also passes the outcome of the lookup to `obs`, which is useful for per-call
+	/// instrumentation. On a hit, the outcome includes the segment the pair was found in and its frequency
+	/// estimate after this access was recorded. `obs` runs before the reference is returned.
+	pub fn get_observed<Q, F>(&mut self, k: &Q, obs: F) -> Option<&V>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+		F: FnOnce(AccessOutcome),
+	{
+		let (v, frequency) = self.lookup(k);
+		let outcome = match v {
+			Some((segment, _)) => AccessOutcome::Hit {
+				segment,
+				frequency: frequency.estimate(k),
+			},
+			None => AccessOutcome::Miss,
+		};
+
+		obs(outcome);
+		v.map(|(_, v)| v)
+	}
+
+	// Looks the key up, recording the access in the statistics and, on a hit, in the approximation sketch.
+	// Returns the segment the pair was found in along with the value. The frequency model is returned as
+	// well so it can still be queried while the value is borrowed.
+	fn lookup<Q>(&mut self, k: &Q) -> Lookup<'_, K, &V, S, C>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		let (v, frequency) = self.lookup_mut(k);
+		(v.map(|(segment, v)| (segment, &*v)), frequency)
+	}
+
+	// Same as `lookup`, but returns a mutable reference to the value.
+	#[inline]
+	fn lookup_mut<Q>(&mut self, k: &Q) -> Lookup<'_, K, &mut V, S, C>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		self.lookup_mut_recording(k, FrequencyModel::increment)
+	}
+
+	// Same as `lookup_mut`, but records a hit in the frequency model with `record`.
+	fn lookup_mut_recording<Q>(
+		&mut self,
+		k: &Q,
+		record: fn(&mut FrequencyModel<K, S, C>, &Q),
+	) -> Lookup<'_, K, &mut V, S, C>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		let (entry, frequency) = self.lookup_slot_recording(k, record);
+		(
+			entry.map(|(segment, entry)| (segment, &mut entry.v)),
+			frequency,
+		)
+	}
+
+	// Same as `lookup_mut_recording`, but returns the whole slot of the pair.
+	fn lookup_slot_recording<Q>(
+		&mut self,
+		k: &Q,
+		record: fn(&mut FrequencyModel<K, S, C>, &Q),
+	) -> Lookup<'_, K, &mut Slot<V>, S, C>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		let entry = match self.window_cache.get_mut(k) {
+			Some(entry) => Some((Segment::Window, entry)),
+			None => self.main_cache.get_mut_located(k),
+		};
+
+		self.stats
+			.record_lookup(entry.as_ref().map(|(segment, _)| *segment));
+		let v = entry.map(|(segment, entry)| {
+			entry.touch(&mut self.sequence);
+			(segment, entry)
+		});
+		if v.is_some() {
+			record(&mut self.frequency, k);
+		}
+
+		(v, &self.frequency)
+	}
+
+	/// Records an access to the key in the approximation sketch like `get` does, without looking the key up.
+	/// The key doesn't have to be stored in the cache.
+	#[inline]
+	pub fn observe<Q>(&mut self, k: &Q)
+	where
+		K: Borrow<Q>,
+		Q: Hash + ?Sized,
+	{
+		self.frequency.increment(k);
+	}
+
+	/// Records `times` accesses to the key in the approximation sketch. The resulting state is the same as
+	/// calling `observe` `times` times, including sketch resets that happen in between.
+	#[inline]
+	pub fn observe_weighted<Q>(&mut self, k: &Q, times: usize)
+	where
+		K: Borrow<Q>,
+		Q: Hash + ?Sized,
+	{
+		self.frequency.increment_by(k, times);
+	}
+
+	/// Records an access to a key that missed the cache like `observe` does and returns whether its estimated
+	/// access frequency is at least `threshold` afterwards. This lets a prefetching layer only prefetch keys
+	/// that keep missing.
+	///
+	/// Lookups don't record misses in the approximation sketch, so this is meant to be called after `get`
+	/// returned `None`. The miss isn't counted in `stats` again.
+	pub fn record_miss_hot<Q>(&mut self, k: &Q, threshold: u16) -> bool
+	where
+		K: Borrow<Q>,
+		Q: Hash + ?Sized,
+	{
+		self.frequency.increment(k);
+		self.frequency.estimate(k) >= threshold
+	}
+
+	/// Retrieves a value for the specified key from the cache and returns an immutable reference if it exists.
+	/// Does not increment pair's count in the approximation sketch.
+	/// If the pair doesn't exist, returns `None`. Never allocates.
+	pub fn peek<Q>(&self, k: &Q) -> Option<&V>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		let entry = match self.window_cache.peek(k) {
+			Some(entry) => Some(entry),
+			None => self.main_cache.peek(k),
+		};
+
+		entry.map(|entry| &entry.v)
+	}
+
+	/// Retrieves a value for the specified key from the cache and returns a mutable reference if it exists.
+	/// Does not increment pair's count in the approximation sketch.
+	/// If the pair doesn't exist, returns `None`.
+	pub fn peek_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		let entry = match self.window_cache.peek_mut(k) {
+			Some(entry) => Some(entry),
+			None => self.main_cache.peek_mut(k),
+		};
+
+		entry.map(|entry| &mut entry.v)
+	}
+
+	/// Returns a reference to the least recently used key-value pair from the window cache, or, if the window
+	/// doesn't use the LRU policy, the pair that it would evict next.
+	/// Returns `None` if the cache is empty.
+	#[inline]
+	pub fn peek_lru_window(&self) -> Option<(&K, &V)> {
+		self.window_cache.peek_lru().map(|(k, entry)| (k, &entry.v))
+	}
+
+	/// Returns the window cache victim and the main cache victim that the next insertion of a new key would
+	/// compare, along with their frequency estimates. The window cache victim is only admitted if its
+	/// estimate is strictly higher.
+	///
+	/// Returns `None` if the window cache or the probationary segment isn't full, since the next insertion
+	/// wouldn't have to make an admission decision then. Doesn't affect recency or the approximation sketch.
+	pub fn admission_candidates(
+		&self,
+	) -> Option<(AdmissionCandidate<'_, K>, AdmissionCandidate<'_, K>)> {
+		if self.window_cache.len() < self.window_cache.cap().get() {
+			return None;
+		}
+
+		let (window_cache_victim_k, _) = self.window_cache.peek_lru()?;
+		let (main_cache_victim_k, _) = self.main_cache.peek_lru_if_full()?;
+		let candidate = |k| AdmissionCandidate {
+			key: k,
+			frequency: self.estimate_frequency(k),
+		};
+
+		Some((
+			candidate(window_cache_victim_k),
+			candidate(main_cache_victim_k),
+		))
+	}
+
+	/// Returns a reference to the least recently used key-value pair from the main cache.
+	/// Returns `None` if the cache is empty.
+	#[inline]
+	pub fn peek_lru_main(&self) -> Option<(&K, &V)> {
+		self.main_cache.peek_lru().map(|(k, entry)| (k, &entry.v))
+	}
+
+	/// Returns a reference to the least recently used key-value pair across all segments, i.e. the pair whose
+	/// last `get` or `put` happened the longest time ago. Returns `None` if the cache is empty.
+	///
+	/// Every pair is stamped with a sequence number on each `get` and `put`, which costs 8 bytes per pair, and
+	/// this visits the whole cache. Requires the `global-lru` feature.
+	#[cfg(feature = "global-lru")]
+	pub fn peek_lru_global(&self) -> Option<(&K, &V)> {
+		self.window_cache
+			.iter()
+			.chain(self.main_cache.iter())
+			.min_by_key(|(_, entry)| entry.seq)
+			.map(|(k, entry)| (k, &entry.v))
+	}
+
+	/// Returns a reference to a key-value pair chosen uniformly at random among all stored pairs, regardless of
+	/// the segment they are stored in. Returns `None` if the cache is empty.
+	///
+	/// Doesn't affect recency or the approximation sketch. Takes time proportional to the number of stored
+	/// pairs. Requires the `rand` feature.
+	#[cfg(feature = "rand")]
+	pub fn peek_random<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> Option<(&K, &V)> {
+		if self.is_empty() {
+			return None;
+		}
+
+		self.iter().nth(rng.gen_range(0..self.len()))
+	}
+
+	/// Returns a bool indicating whether a key-value pair stored in the cache. Never allocates.
+	pub fn contains<Q>(&self, k: &Q) -> bool
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		match self.window_cache.contains(k) {
+			true => true,
+			false => self.main_cache.contains(k),
+		}
+	}
+
+	/// Returns the hash the cache uses to look the key up. Every segment of a cache uses the cache's hasher
+	/// (`S`), so different caches hash the same key identically if they were built with the same hasher, e.g.
+	/// a deterministic one. With the default `RandomState` every cache is seeded differently.
+	///
+	/// This is useful to route keys to shards consistently with the hashing of a cache, e.g. with
+	/// `hash_key(k) % shard_count`. It's unrelated to the hashes of the approximation sketch.
+	pub fn hash_key<Q>(&self, k: &Q) -> u64
+	where
+		K: Borrow<Q>,
+		Q: Hash + ?Sized,
+	{
+		self.hash_builder.hash_one(k)
+	}
+
+	/// Returns whether each of the keys is stored in the cache, in the same order as the keys.
+	pub fn contains_mask<'a, I, Q>(&self, keys: I) -> Vec<bool>
+	where
+		I: IntoIterator<Item = &'a Q>,
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized + 'a,
+	{
+		keys.into_iter().map(|k| self.contains(k)).collect()
+	}
+
+	/// Returns a bool indicating whether a key-value pair is stored in the protected segment of the main cache,
+	/// i.e. whether it was promoted out of the probationary segment.
+	/// Doesn't affect recency or the approximation sketch.
+	pub fn is_protected<Q>(&self, k: &Q) -> bool
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		self.main_cache.is_protected(k)
+	}
+
+	/// Returns the recency rank of the key within the segment it's stored in along with the length of that
+	/// segment, or `None` if the key isn't stored in the cache. Rank `0` is the most recently used pair and
+	/// `len - 1` the least recently used one. For a window that doesn't use the LRU policy, the rank counts
+	/// from the most recently inserted pair instead.
+	///
+	/// Doesn't affect recency or the approximation sketch. Takes time proportional to the length of the
+	/// segment.
+	pub fn lru_rank<Q>(&self, k: &Q) -> Option<(usize, usize)>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		match self.window_cache.rank(k) {
+			Some(rank) => Some((rank, self.window_cache.len())),
+			None => self.main_cache.rank(k).map(|(_, rank, len)| (rank, len)),
+		}
+	}
+
+	/// Returns the estimated access frequency, the segment and the recency rank of a stored key, or `None` if
+	/// the key isn't stored in the cache. The rank is the one `lru_rank` returns.
+	///
+	/// Doesn't affect recency or the approximation sketch. The segments are searched once, so this takes time
+	/// proportional to the length of the segment the key is stored in and of the segments searched before it.
+	pub fn metadata<Q>(&self, k: &Q) -> Option<EntryMetadata>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		let (segment, lru_rank) = match self.window_cache.rank(k) {
+			Some(rank) => (Segment::Window, rank),
+			None => {
+				let (segment, rank, _) = self.main_cache.rank(k)?;
+				(segment, rank)
+			}
+		};
+
+		Some(EntryMetadata {
+			frequency: self.estimate_frequency(k),
+			segment,
+			lru_rank,
+		})
+	}
+
+	/// Moves a key-value pair from the probationary to the protected segment of the main cache without
+	/// accessing it, so neither the approximation sketch nor the statistics are updated. If the protected
+	/// segment is full, its least recently used pair is demoted to the probationary segment.
+	/// Returns whether the pair was promoted, i.e. `false` if it isn't stored in the probationary segment.
+	pub fn promote<Q>(&mut self, k: &Q) -> bool
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		self.main_cache.promote(k)
+	}
+
+	/// Removes a key-value pair with the specified key and returns pair's value.
+	pub fn pop<Q>(&mut self, k: &Q) -> Option<V>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		let entry = match self.window_cache.pop(k) {
+			Some(entry) => Some(entry),
+			None => self.main_cache.pop(k),
+		};
+
+		entry.map(|entry| entry.v)
+	}
+
+	/// Removes a key-value pair with the specified key and returns the pair.
+	pub fn pop_entry<Q>(&mut self, k: &Q) -> Option<(K, V)>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		let entry = match self.window_cache.pop_entry(k) {
+			Some(entry) => Some(entry),
+			None => self.main_cache.pop_entry(k),
+		};
+
+		entry.map(|(k, entry)| (k, entry.v))
+	}
+
+	/// Removes the least recently used key-value pair from the window cache and returns the pair.
+	/// If the window doesn't use the LRU policy, removes the pair that it would evict next instead.
+	pub fn pop_lru_window(&mut self) -> Option<(K, V)> {
+		self.window_cache.pop_lru().map(|(k, entry)| (k, entry.v))
+	}
+
+	/// Removes the least recently used key-value pair from the main cache and returns the pair.
+	pub fn pop_lru_main(&mut self) -> Option<(K, V)> {
+		self.main_cache.pop_lru().map(|(k, entry)| (k, entry.v))
+	}
+
+	/// Works like `pop_lru_window`, but also returns the estimated access frequency of the removed key, e.g. to
+	/// decide whether it's worth keeping in a lower cache tier. Removing a pair doesn't change its estimate.
+	pub fn pop_lru_window_with_frequency(&mut self) -> Option<(K, V, u16)> {
+		let (k, v) = self.pop_lru_window()?;
+		let frequency = self.estimate_frequency(&k);
+
+		Some((k, v, frequency))
+	}
+
+	/// Works like `pop_lru_main`, but also returns the estimated access frequency of the removed key.
+	pub fn pop_lru_main_with_frequency(&mut self) -> Option<(K, V, u16)> {
+		let (k, v) = self.pop_lru_main()?;
+		let frequency = self.estimate_frequency(&k);
+
+		Some((k, v, frequency))
+	}
+
+	/// Removes up to `count` key-value pairs and returns them in the order they were removed.
+	///
+	/// The segments are drained one after another, coldest first: the probationary segment, then the window
+	/// cache and finally the protected segment, each in the order it would evict its pairs. This doesn't merge
+	/// the segments by recency, but the pairs that were accessed repeatedly and promoted go last. Calling this
+	/// in a loop before shrinking the cache with `resize` spreads the cost of a large resize over several
+	/// calls. Removed pairs aren't passed to the eviction callback.
+	pub fn evict_lru_batch(&mut self, count: usize) -> Vec<(K, V)> {
+		let mut evicted = Vec::with_capacity(cmp::min(count, self.len()));
+		while evicted.len() < count {
+			let pair = self
+				.main_cache
+				.pop_lru_probationary()
+				.map(|(k, entry)| (k, entry.v))
+				.or_else(|| self.pop_lru_window())
+				.or_else(|| self.pop_lru_main());
+			match pair {
+				Some(pair) => evicted.push(pair),
+				None => break,
+			}
+		}
+
+		evicted
+	}
+
+	/// Removes pairs until at most `target_len` are left and returns them in the order they were removed. The
+	/// capacity is left unchanged, unlike with `resize`.
+	///
+	/// Pairs are removed in the same segment order as `evict_lru_batch` removes them: the probationary
+	/// segment first, then the window cache and the protected segment last, each in the order it would evict
+	/// its pairs. Frequencies aren't consulted. Removed pairs aren't passed to the eviction callback.
+	pub fn shrink_to(&mut self, target_len: usize) -> Vec<(K, V)> {
+		self.evict_lru_batch(self.len().saturating_sub(target_len))
+	}
+
+	/// Returns the number of stored key-value pairs.
+	pub fn len(&self) -> usize {
+		self.window_cache.len() + self.main_cache.len()
+	}
+
+	/// Returns whether `len` matches the number of pairs `iter` yields. Both always agree unless moving a pair
+	/// between segments lost or duplicated it, so this is meant for tests and debugging. Takes time
+	/// proportional to the number of stored pairs.
+	pub fn verify_len(&self) -> bool {
+		self.len() == self.iter().count()
+	}
+
+	/// Returns a bool indicating whether the cache is empty.
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Returns the capacity of the cache (the maximum number of key-value pairs that the cache can store).
+	///
+	/// This is the value passed to `new` or `resize`. Every segment can hold at least one pair, so caches with
+	/// a capacity below 3 can hold 3 pairs. Otherwise the capacities of the segments add up to this value,
+	/// regardless of the window and protected ratios.
+	pub fn cap(&self) -> usize {
+		self.cap
+	}
+
+	/// Returns the capacity of the window cache.
+	#[inline]
+	pub fn window_cap(&self) -> usize {
+		self.window_cache.cap().get()
+	}
+
+	/// Returns the capacity of the main cache, i.e. of the probationary and the protected segment together.
+	#[inline]
+	pub fn main_cap(&self) -> usize {
+		self.main_cache.cap()
+	}
+
+	/// Returns the number of pairs stored in the window cache.
+	#[inline]
+	pub fn window_len(&self) -> usize {
+		self.window_cache.len()
+	}
+
+	/// Returns the number of pairs stored in the main cache, i.e. in the probationary and the protected segment
+	/// together.
+	#[inline]
+	pub fn main_len(&self) -> usize {
+		self.main_cache.len()
+	}
+
+	/// Returns the number of pairs stored in the probationary segment of the main cache.
+	#[inline]
+	pub fn probationary_len(&self) -> usize {
+		self.main_cache.probationary_len()
+	}
+
+	/// Returns the number of pairs stored in the protected segment of the main cache.
+	#[inline]
+	pub fn protected_len(&self) -> usize {
+		self.main_cache.protected_len()
+	}
+
+	/// Resizes the cache. If the new capacity is smaller than the size of the current cache any entries past the new capacity are discarded.
+	///
+	/// Existing entries are redistributed eagerly, so every segment fits its new capacity once this returns:
+	/// 1. The main cache is resized first. Entries that don't fit into its protected segment anymore are
+	///    demoted to the probationary segment, and the least recently used probationary entries are discarded.
+	/// 2. The least recently used entries that don't fit into the window cache anymore are then offered to
+	///    the main cache through the usual admission policy, and discarded if they lose.
+	///
+	/// An enlarged protected segment is filled by promotions as usual, entries aren't moved into it eagerly.
+	pub fn resize(&mut self, cap: usize) {
+		self.resize_checked(cap);
+	}
+
+	/// Resizes the cache like `resize` and returns whether any pair was discarded, which never happens when
+	/// the cache grows. Since every segment gets a fixed share of the capacity, shrinking can discard pairs
+	/// even if the new capacity is still larger than `len`.
+	pub fn resize_checked(&mut self, cap: usize) -> bool {
+		let evicted = self.resize_evicting(cap);
+		let discarded = !evicted.is_empty();
+		for (k, v) in evicted {
+			self.evict(k, v, EvictReason::Resize);
+		}
+
+		discarded
+	}
+
+	/// Resizes the cache like `resize`, but returns the discarded pairs instead of passing them to the eviction
+	/// callback. This includes the pairs dropped from both main cache segments as well as the window pairs
+	/// that lost the admission contest, and the main cache pairs they pushed out if they won.
+	pub fn resize_evicting(&mut self, cap: usize) -> Vec<(K, V)> {
+		let (window_cache_cap, main_cache_cap) = segment_caps(cap, self.window_ratio);
+
+		self.cap = cap;
+		let evicted = self.resize_segments(window_cache_cap, main_cache_cap);
+		debug_assert_eq!(
+			self.window_cache.cap().get() + self.main_cache.cap(),
+			cmp::max(3, cap)
+		);
+
+		evicted
+	}
+
+	/// Sets the capacity of the window cache to exactly `window_cap` pairs and gives the rest of `cap` to the
+	/// main cache. The pairs that don't fit anymore are redistributed and evicted like with `resize`.
+	///
+	/// The split only lasts until the next `resize` or `grow_to`, which split the new capacity according to
+	/// the window ratio again.
+	///
+	/// # Panics
+	///
+	/// Panics if `window_cap` is `0` or leaves less than 2 pairs for the main cache, which needs room for a
+	/// pair in each of its segments.
+	pub fn set_window_capacity(&mut self, window_cap: usize) {
+		assert!(
+			window_cap > 0 && window_cap <= self.cap.saturating_sub(2),
+			"window capacity must be at least 1 and leave room for 2 pairs in the main cache"
+		);
+
+		let window_cache_cap = NonZeroUsize::new(window_cap).expect("non zero size");
+		for (k, v) in self.resize_segments(window_cache_cap, self.cap - window_cap) {
+			self.evict(k, v, EvictReason::Resize);
+		}
+	}
+
+	// Resizes the main cache and then the window cache, offering the window overflow to the main cache.
+	// Returns the pairs that don't fit anymore, without passing them to the eviction callback.
+	fn resize_segments(
+		&mut self,
+		window_cache_cap: NonZeroUsize,
+		main_cache_cap: usize,
+	) -> Vec<(K, V)> {
+		let mut evicted: Vec<(K, V)> = self
+			.main_cache
+			.resize(main_cache_cap)
+			.into_iter()
+			.map(|(k, entry)| (k, entry.v))
+			.collect();
+
+		while self.window_cache.len() > window_cache_cap.get() {
+			if let Some((k, entry)) = self.window_cache.pop_lru() {
+				if let Some((k, entry, _)) = self.admit(k, entry) {
+					evicted.push((k, entry.v));
+				}
+			}
+		}
+
+		self.window_cache.resize(window_cache_cap);
+		self.debug_assert_capacity_invariant();
+
+		evicted
+	}
+
+	/// Resizes the cache like `resize` and then updates the approximation sketch according to `policy`.
+	///
+	/// The sketch is only updated after the pairs were redistributed, so admission decisions made while
+	/// shrinking still use the old counts. `Decay` leaves the doorkeeper untouched, while `Reset` replaces the
+	/// whole frequency model with an empty one of the same sample size.
+	pub fn resize_with_policy(&mut self, cap: usize, policy: ResizeFrequencyPolicy) {
+		self.resize(cap);
+
+		match policy {
+			ResizeFrequencyPolicy::Keep => {}
+			ResizeFrequencyPolicy::Decay => self.frequency.decay(0.5),
+			ResizeFrequencyPolicy::Reset => {
+				self.frequency = self.empty_frequency(self.frequency.sample_size())
+			}
+		}
+	}
+
+	/// Resizes the cache like `resize`, but never evicts a pair. Requests smaller than the number of stored
+	/// pairs are ignored and the cache is only shrunk down to that number.
+	///
+	/// Since every segment gets a fixed share of the capacity, a segment can hold more pairs than its share of
+	/// `max(cap, self.len())`. In that case the smallest larger capacity that keeps every pair is used
+	/// instead. The cache is never shrunk to less than that, and only grown past `cap` or the current
+	/// capacity, whichever is larger, if the pairs don't fit into it when it's split according to the window
+	/// ratio. That happens after `set_window_capacity` gave the window more than its share.
+	pub fn grow_to(&mut self, cap: usize) {
+		let fits = |cap: usize| {
+			let (window_cache_cap, main_cache_cap) = segment_caps(cap, self.window_ratio);
+			self.window_cache.len() <= window_cache_cap.get()
+				&& self.main_cache.fits(main_cache_cap)
+		};
+
+		let mut low = cmp::max(cap, self.len());
+		let mut high = cmp::max(low, self.cap);
+		// The split of `set_window_capacity` is undone, so the current capacity doesn't necessarily fit.
+		while !fits(high) {
+			low = high + 1;
+			high = high.checked_mul(2).expect("capacity overflow");
+		}
+		while low < high {
+			let mid = low + (high - low) / 2;
+			if fits(mid) {
+				high = mid;
+			} else {
+				low = mid + 1;
+			}
+		}
+
+		self.resize(low);
+	}
+
+	/// Changes the share of the main cache's capacity that goes to the protected segment, the rest goes to
+	/// the probationary segment. The default is `0.8`, or the ratio passed to
+	/// [`WTinyLfuCacheBuilder::protected_ratio`]. The window isn't affected.
+	///
+	/// Both segments are resized right away, like with `resize`: entries that don't fit into the protected
+	/// segment anymore are demoted to the probationary segment, and the least recently used probationary
+	/// entries that don't fit are evicted. A larger protected segment is filled by promotions as usual.
+	///
+	/// # Panics
+	///
+	/// Panics if `ratio` isn't strictly between `0.0` and `1.0`.
+	pub fn set_protected_ratio(&mut self, ratio: f64) {
+		assert!(
+			ratio > 0.0 && ratio < 1.0,
+			"protected ratio must be strictly between 0.0 and 1.0"
+		);
+
+		// Keeps the split of `set_window_capacity`.
+		let main_cache_cap = self.main_cache.cap();
+		for (k, entry) in self.main_cache.set_protected_ratio(ratio, main_cache_cap) {
+			self.evict(k, entry.v, EvictReason::Resize);
+		}
+	}
+
+	/// Marks a key stored in the main cache so it's never demoted out of the protected segment, promoting it if
+	/// it's stored in the probationary segment. Returns whether the key is marked. Doesn't count as an access.
+	///
+	/// Returns `false` if the key isn't stored in the main cache, which includes keys that are still in the
+	/// window cache, or if marking it would leave no unmarked pair in the protected segment. Promotions demote
+	/// the least recently used unmarked pair instead.
+	///
+	/// Marked pairs aren't evicted under capacity pressure, since new pairs only ever evict probationary ones.
+	/// They can still be removed explicitly, like with `pop` or `pop_lru_main` once the probationary segment
+	/// is empty, which drops the mark. Shrinking the protected segment with `resize` or `set_protected_ratio`
+	/// demotes marked pairs only after all unmarked ones, and drops the marks that no longer fit.
+	pub fn protect_key<Q>(&mut self, k: &Q) -> bool
+	where
+		K: Borrow<Q> + Clone,
+		Q: Hash + Eq + ?Sized,
+	{
+		self.main_cache.protect(k)
+	}
+
+	/// Removes the window cache pairs whose estimated access frequency is below `threshold` and returns them,
+	/// in the order the window would have evicted them. This is meant to clean up after a known scan filled
+	/// the window with keys that are accessed only once.
+	///
+	/// Only the window cache is touched: the main cache, the approximation sketch and the recency of the
+	/// remaining pairs stay the same. Removed pairs aren't passed to the eviction callback.
+	pub fn prune_cold_window(&mut self, threshold: u16) -> Vec<(K, V)> {
+		let frequency = &self.frequency;
+		self.window_cache
+			.extract(|k, _| frequency.estimate(k) < threshold)
+			.into_iter()
+			.map(|(k, entry)| (k, entry.v))
+			.collect()
+	}
+
+	/// Removes all key-value pairs from the cache.
+	pub fn clear(&mut self) {
+		if self.on_evict.is_some() {
+			while let Some((k, entry)) = self.window_cache.pop_lru() {
+				self.evict(k, entry.v, EvictReason::Clear);
+			}
+			while let Some((k, entry)) = self.main_cache.pop_lru() {
+				self.evict(k, entry.v, EvictReason::Clear);
+			}
+
+			return;
+		}
+
+		self.window_cache.clear();
+		self.main_cache.clear();
+	}
+
+	/// Calls `cond` with the cache and, if it returns `true`, removes all key-value pairs like `clear` does and
+	/// resets the approximation sketch and the doorkeeper, so the cache forgets every frequency as well.
+	/// Returns whether the cache was cleared.
+	pub fn clear_if<F: FnOnce(&Self) -> bool>(&mut self, cond: F) -> bool {
+		if !cond(self) {
+			return false;
+		}
+
+		self.clear();
+		self.frequency = self.empty_frequency(self.frequency.sample_size());
+		true
+	}
+
+	/// Removes all key-value pairs from the cache and returns them as an iterator, window cache pairs first.
+	///
+	/// The approximation sketch and the doorkeeper are reset right away, so the cache also forgets the
+	/// frequencies of the drained keys. The pairs that aren't consumed are removed like `clear` does when the
+	/// iterator is dropped, so they're passed to the eviction callback with [`EvictReason::Clear`]. The yielded
+	/// pairs aren't. If the iterator is leaked, the pairs it didn't yield stay in the cache.
+	pub fn drain(&mut self) -> Drain<'_, K, V, S, C> {
+		self.frequency = self.empty_frequency(self.frequency.sample_size());
+		Drain::new(self)
+	}
+
+	/// Keeps only the pairs for which `f` returns `true`. The survivors keep their segment and their relative
+	/// order. Removing pairs isn't an access, so the approximation sketch is left untouched and removed keys
+	/// keep their counts. Removed pairs aren't passed to the eviction callback.
+	///
+	/// Works in place: every segment is walked once, from its least recently used pair on, and only the pairs
+	/// `f` rejects are removed. Neither the survivors nor the removed keys are collected, so nothing is
+	/// allocated.
+	pub fn retain<F: FnMut(&K, &mut V) -> bool>(&mut self, mut f: F) {
+		self.window_cache.retain(|k, entry| f(k, &mut entry.v));
+		self.main_cache.retain(|k, entry| f(k, &mut entry.v));
+	}
+
+	/// Keeps only the pairs for which `f` returns `true` and rebuilds the approximation sketch so it only
+	/// remembers the survivors.
+	///
+	/// Removing pairs otherwise leaves the sketch untouched, so removed keys keep their counts until the next
+	/// reset and are favoured by the admission policy if they come back. This variant clears the sketch and the
+	/// doorkeeper afterwards and records the survivors' previous estimates again. Removed pairs aren't passed
+	/// to the eviction callback.
+	pub fn retain_and_forget<F: FnMut(&K, &mut V) -> bool>(&mut self, f: F) {
+		self.retain(f);
+
+		self.reseed_frequency(self.empty_frequency(self.frequency.sample_size()));
+	}
+
+	/// Rebuilds the approximation sketch and the doorkeeper for a different sample size, keeping the relative
+	/// popularity of the stored keys.
+	///
+	/// The new sketch is seeded with the current estimates of the stored keys, so keys that aren't stored in
+	/// the cache are forgotten. This is approximate: the estimates already include collisions, and seeding a
+	/// smaller sample can fill it up and halve the new counters right away.
+	pub fn resize_sketch(&mut self, new_sample_size: usize) {
+		self.reseed_frequency(self.empty_frequency(new_sample_size));
+	}
+
+	/// Shrinks the approximation sketch and the doorkeeper to the sample size recommended for the number of
+	/// distinct keys the cache actually sees, like `resize_sketch` does. Useful for caches that were created
+	/// with a much larger `sample_size` than their key set needs.
+	///
+	/// The number of distinct keys is the larger of `approx_working_set` and `len`. The sketch is never grown,
+	/// so this does nothing if the current sample size isn't larger than the recommended one. Accuracy only
+	/// suffers if the key set grows later, in which case `resize_sketch` can grow the sketch again.
+	pub fn compact_sketch(&mut self) {
+		let distinct_keys = cmp::max(self.approx_working_set(), self.len());
+		let sample_size = WTinyLfuCache::<K, V>::recommended_sample_size(distinct_keys);
+		if sample_size < self.frequency.sample_size() {
+			self.resize_sketch(sample_size);
+		}
+	}
+
+	// Creates an empty frequency model for `sample_size` that ages its counts like the current one.
+	fn empty_frequency(&self, sample_size: usize) -> FrequencyModel<K, S, C> {
+		let mut model = FrequencyModel::new(sample_size, self.hash_builder.clone());
+		model.set_decay_strategy(self.frequency.decay_strategy());
+		model
+	}
+
+	// Records the current estimate of every stored key in `model` and replaces the frequency model with it.
+	fn reseed_frequency(&mut self, mut model: FrequencyModel<K, S, C>) {
+		for (k, frequency) in self.estimate_all() {
+			model.increment_by(k, frequency as usize);
+		}
+
+		self.frequency = model;
+	}
+
+	// Returns every stored key along with its estimated access frequency, in `iter` order. Visits the segments
+	// in a single pass and queries the sketch and the doorkeeper once per key. Doesn't count as an access.
+	fn estimate_all(&self) -> Vec<(&K, u16)> {
+		self.keys()
+			.map(|k| (k, self.frequency.estimate(k)))
+			.collect()
+	}
+
+	/// Returns the estimated access frequency of the key that is used for admission decisions.
+	/// Keys that were never accessed within the current sample are estimated as `0`.
+	#[inline]
+	pub fn estimate_frequency<Q>(&self, k: &Q) -> u16
+	where
+		K: Borrow<Q>,
+		Q: Hash + ?Sized,
+	{
+		self.frequency.estimate(k)
+	}
+
+	/// Multiplies every counter of the approximation sketch by `factor`, rounding down, which makes the cache
+	/// partially forget past accesses without waiting for the next reset. The doorkeeper isn't affected.
+	///
+	/// The sketch is shared by all segments, so this affects the estimates of every key, not just the ones of
+	/// a single segment. The relative order of the estimates is preserved.
+	///
+	/// # Panics
+	///
+	/// Panics if `factor` isn't within `0.0..=1.0`.
+	pub fn decay_frequency(&mut self, factor: f64) {
+		assert!(
+			(0.0..=1.0).contains(&factor),
+			"decay factor must be within 0.0..=1.0"
+		);
+		self.frequency.decay(factor);
+	}
+
+	/// Returns an owned copy of the frequency model, i.e. the approximation sketch, the doorkeeper and the
+	/// progress of the current sample, without any of the stored pairs.
+	pub fn clone_frequency_model(&self) -> FrequencyModel<K, S, C>
+	where
+		C: Clone,
+	{
+		self.frequency.clone()
+	}
+
+	/// Replaces the frequency model of the cache, so a fresh cache can start with the popularity learned by
+	/// another one. Stored pairs aren't affected.
+	///
+	/// The model keeps its own sketch dimensions and sample size, which replace the ones of this cache. To
+	/// keep the sketch sized for this cache, import models from caches created with the same `sample_size`.
+	pub fn import_frequency(&mut self, model: FrequencyModel<K, S, C>) {
+		self.frequency = model;
+	}
+
+	/// Copies the stored pairs into a plain [`LruCache`] with room for all of them, for code that expects one.
+	///
+	/// The pairs are ordered like `iter` yields them, so the window cache pairs are the most recently used
+	/// ones, followed by the main cache pairs. Frequencies and segments are lost.
+	pub fn to_lru(&self) -> LruCache<K, V>
+	where
+		K: Clone,
+		V: Clone,
+	{
+		let cap = NonZeroUsize::new(cmp::max(1, self.len())).expect("non zero size");
+		let mut lru = LruCache::new(cap);
+		let pairs: Vec<(&K, &V)> = self.iter().collect();
+		for (k, v) in pairs.into_iter().rev() {
+			lru.push(k.clone(), v.clone());
+		}
+
+		lru
+	}
+
+	/// Consumes the cache and returns a read-only [`FrozenCache`] that can't modify entries, their recency
+	/// or the approximation sketch.
+	pub fn freeze(self) -> FrozenCache<K, V, S, C> {
+		FrozenCache::new(self)
+	}
+
+	/// Returns the hit and miss counters collected by lookups like `get` and `get_mut`, along with the outcomes
+	/// of the admission decisions made by inserts like `put` and `push`.
+	#[inline]
+	pub fn stats(&self) -> Stats {
+		self.stats
+	}
+
+	/// Returns the share of all lookups that found the key in the cache, see [`Stats::hit_ratio`].
+	#[inline]
+	pub fn hit_ratio(&self) -> f64 {
+		self.stats.hit_ratio()
+	}
+
+	/// Sets all counters returned by `stats` back to zero.
+	#[inline]
+	pub fn reset_stats(&mut self) {
+		self.stats = Stats::default();
+	}
+
+	/// Returns an estimate of the working set size: the number of distinct keys that were accessed more
+	/// than once within the current sample.
+	///
+	/// A key is counted the first time an access gets past the doorkeeper within the sample, no matter how
+	/// often it was accessed in earlier samples. The estimate resets together with the doorkeeper every
+	/// `sample_size` counted accesses, and false positives of the filter that remembers the counted keys
+	/// may cause it to undercount slightly.
+	pub fn approx_working_set(&self) -> usize {
+		self.frequency.reused_keys()
+	}
+
+	/// Returns the Jaccard similarity of the keys stored in both caches: the number of keys stored in both
+	/// divided by the number of keys stored in either. `1.0` means both store the same keys, `0.0` means they
+	/// share none. Two empty caches are considered equal. Values, segments and frequencies are ignored, and
+	/// neither cache records an access.
+	///
+	/// Useful in tests that only expect two caches to end up with mostly the same contents.
+	pub fn contents_similarity(&self, other: &Self) -> f64 {
+		let shared = self.keys().filter(|k| other.contains(*k)).count();
+		let union = self.len() + other.len() - shared;
+		if union == 0 {
+			return 1.0;
+		}
+
+		shared as f64 / union as f64
+	}
+
+	/// An iterator visiting all entries in roughly most-recently used order.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use wtinylfu::WTinyLfuCache;
+	///
+	/// let mut cache = WTinyLfuCache::new(3, 10);
+	/// cache.put("a", 1);
+	/// cache.put("b", 2);
+	/// cache.put("c", 3);
+	///
+	/// for (key, val) in cache.iter() {
+	///     println!("key: {} val: {}", key, val);
+	/// }
+	/// ```
+	pub fn iter(&self) -> Iter<'_, K, V> {
+		Iter::new(self)
+	}
+
+	/// An iterator visiting all keys in the same order as `iter`. Doesn't count as an access.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use wtinylfu::WTinyLfuCache;
+	///
+	/// let mut cache = WTinyLfuCache::new(3, 10);
+	/// cache.put("a", 1);
+	/// cache.put("b", 2);
+	/// cache.put("c", 3);
+	///
+	/// for key in cache.keys() {
+	///     println!("key: {}", key);
+	/// }
+	/// ```
+	pub fn keys(&self) -> impl Iterator<Item = &K> {
+		self.iter().map(|(k, _)| k)
+	}
+
+	/// An iterator visiting all values in the same order as `iter`. Doesn't count as an access.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use wtinylfu::WTinyLfuCache;
+	///
+	/// let mut cache = WTinyLfuCache::new(3, 10);
+	/// cache.put("a", 1);
+	/// cache.put("b", 2);
+	/// cache.put("c", 3);
+	///
+	/// for val in cache.values() {
+	///     println!("val: {}", val);
+	/// }
+	/// ```
+	pub fn values(&self) -> impl Iterator<Item = &V> {
+		self.iter().map(|(_, v)| v)
+	}
+
+	/// An iterator visiting all entries with mutable references to their values. Like `peek_mut`, this
+	/// doesn't count as an access and doesn't change the eviction order. The entries are visited in no
+	/// particular order.
+	pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+		self.window_cache
+			.iter_mut()
+			.chain(self.main_cache.iter_mut())
+			.map(|(k, entry)| (k, &mut entry.v))
+	}
+
+	/// An iterator visiting mutable references to all values. Doesn't count as an access and doesn't change
+	/// the eviction order. The values are visited in no particular order.
+	pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+		self.iter_mut().map(|(_, v)| v)
+	}
+
+	/// Consumes the cache and returns all of its pairs along with their estimated access frequencies, sorted by
+	/// descending frequency. Pairs with equal estimates are in no particular order.
+	///
+	/// The estimates are taken before the approximation sketch is dropped. This allocates a vector for all
+	/// pairs and sorts it, which takes `O(n log n)` time.
+	pub fn into_sorted_by_frequency_desc(self) -> Vec<(K, V, u16)> {
+		let mut entries = self.into_estimated();
+		entries.sort_by_key(|(_, _, frequency)| cmp::Reverse(*frequency));
+
+		entries
+	}
+
+	/// Consumes the cache and returns an iterator over its pairs sorted by ascending estimated access
+	/// frequency, so the hottest pairs come last. Useful to spill into a bounded lower tier that keeps the pairs
+	/// it receives last. Pairs with equal estimates keep the order of [`IntoIter`], so the order is
+	/// deterministic.
+	///
+	/// Like `into_sorted_by_frequency_desc`, this collects and sorts all pairs up front.
+	pub fn into_iter_coldest_first(self) -> impl Iterator<Item = (K, V)> {
+		let mut entries = self.into_estimated();
+		entries.sort_by_key(|(_, _, frequency)| *frequency);
+
+		entries.into_iter().map(|(k, v, _)| (k, v))
+	}
+
+	// Consumes the cache and returns its pairs in `into_iter` order, each with its estimated access frequency.
+	fn into_estimated(mut self) -> Vec<(K, V, u16)> {
+		let mut entries = Vec::with_capacity(self.len());
+		while let Some((k, entry)) = self.window_cache.pop_lru() {
+			let frequency = self.frequency.estimate(&k);
+			entries.push((k, entry.v, frequency));
+		}
+		while let Some((k, entry)) = self.main_cache.pop_lru() {
+			let frequency = self.frequency.estimate(&k);
+			entries.push((k, entry.v, frequency));
+		}
+
+		entries
+	}
+
+	/// An iterator visiting all entries along with the segment they are stored in. The window cache entries
+	/// come first, followed by the probationary and then the protected ones, each group in the same order
+	/// `iter` visits them.
+	pub fn iter_by_segment(&self) -> impl Iterator<Item = (Segment, &K, &V)> {
+		self.window_cache
+			.iter()
+			.map(|(k, entry)| (Segment::Window, k, entry))
+			.chain(self.main_cache.iter_located())
+			.map(|(segment, k, entry)| (segment, k, &entry.v))
+	}
+
+	/// Returns up to `n` entries without visiting the rest of the cache.
+	///
+	/// The entries are taken in the same order as `iter` yields them, so this is a quick preview of the cache
+	/// contents rather than a representative sample.
+	pub fn sample_entries(&self, n: usize) -> Vec<(&K, &V)> {
+		self.iter().take(n).collect()
+	}
+}
+
+/// Inserts the pairs with `put`, in iteration order. Pairs evicted to make room are passed to the eviction
+/// callback.
+impl<K: Hash + Eq, V, S: BuildHasher + Clone, C: FrequencySketch<K, S>> Extend<(K, V)>
+	for WTinyLfuCache<K, V, S, C>
+{
+	fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+		for (k, v) in iter {
+			self.put(k, v);
+		}
+	}
+}
+
+/// Creates a cache with room for exactly the collected pairs and the recommended sample size for that capacity,
+/// see `recommended_sample_size`.
+///
+/// Every pair is kept. Unlike with `with_entries`, the pairs skip the admission policy, which would otherwise
+/// reject most of them since none were accessed yet. Later pairs with a duplicate key replace the value.
+impl<K: Hash + Eq, V> FromIterator<(K, V)> for WTinyLfuCache<K, V> {
+	fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+		let entries: Vec<(K, V)> = iter.into_iter().collect();
+		let cap = cmp::max(1, entries.len());
+		let mut cache = Self::new(cap, Self::recommended_sample_size(cap));
+		for (k, v) in entries {
+			cache.fill(k, v);
+		}
+
+		cache
+	}
+}
+
+/// Deep copies the segments, the sketch and the doorkeeper, so the clone evicts and admits exactly like the
+/// original would. Statistics are copied too. The eviction callback can't be cloned, so the clone has none.
+impl<K, V, S, C> Clone for WTinyLfuCache<K, V, S, C>
+where
+	K: Hash + Eq + Clone,
+	V: Clone,
+	S: BuildHasher + Clone,
+	C: FrequencySketch<K, S> + Clone,
+{
+	fn clone(&self) -> Self {
+		Self {
+			cap: self.cap,
+			frequency: self.frequency.clone(),
+			window_cache: self
+				.window_cache
+				.clone_with_hasher(self.hash_builder.clone()),
+			main_cache: self.main_cache.clone_with_hasher(self.hash_builder.clone()),
+			hash_builder: self.hash_builder.clone(),
+			window_ratio: self.window_ratio,
+			sequence: self.sequence,
+			stats: self.stats,
+			on_evict: None,
+		}
+	}
+}
+
+// Number of entries the alternate `Debug` format prints at most.
+const DEBUG_ENTRIES: usize = 10;
+
+// Prints the sizes and the sample progress. The alternate format also prints the first few entries in `iter`
+// order. The sketch and the doorkeeper are left out, they're just counters and bits.
+impl<K, V, S, C> fmt::Debug for WTinyLfuCache<K, V, S, C>
+where
+	K: Hash + Eq + fmt::Debug,
+	V: fmt::Debug,
+	S: BuildHasher + Clone,
+	C: FrequencySketch<K, S>,
+{
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let alternate = f.alternate();
+		let mut debug = f.debug_struct("WTinyLfuCache");
+		debug
+			.field("cap", &self.cap)
+			.field("len", &self.len())
+			.field("window_len", &self.window_cache.len())
+			.field("main_len", &self.main_cache.len())
+			.field("sample_size", &self.frequency.sample_size())
+			.field("sample_counter", &self.frequency.sample_counter());
+
+		if alternate {
+			debug.field("entries", &DebugEntries(self));
+		}
+
+		debug.finish_non_exhaustive()
+	}
+}
+
+struct DebugEntries<'a, K: Hash + Eq, V, S, C>(&'a WTinyLfuCache<K, V, S, C>);
+
+impl<K, V, S, C> fmt::Debug for DebugEntries<'_, K, V, S, C>
+where
+	K: Hash + Eq + fmt::Debug,
+	V: fmt::Debug,
+	S: BuildHasher + Clone,
+	C: FrequencySketch<K, S>,
+{
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_map()
+			.entries(self.0.iter().take(DEBUG_ENTRIES))
+			.finish()
+	}
+}
+
+// Splits the capacity of the cache into the capacities of the window and the main cache.
+fn segment_caps(cap: usize, window_ratio: f64) -> (NonZeroUsize, usize) {
+	let f64_cap: f64 = cap as f64;
+	// The window never takes the two slots the segments of the main cache need at least.
+	let window_cache_cap = cmp::min((f64_cap * window_ratio) as usize, cap.saturating_sub(2));
+	let window_cache_cap = NonZeroUsize::new(cmp::max(1, window_cache_cap)).expect("non zero size");
+	let main_cache_cap = cmp::max(1, cap.saturating_sub(window_cache_cap.get()));
+
+	(window_cache_cap, main_cache_cap)
+}
+
+impl<K: Hash + Eq> WTinyLfuCache<K, ()> {
+	/// Replays an access trace against a temporary cache with the specified capacity and sample size and
+	/// returns the observed hit ratio. Each key is looked up with `get` and inserted on a miss.
+	///
+	/// This is useful to predict the effect of a different capacity before resizing a cache in production.
+	/// It takes time proportional to the length of the trace. Returns `0.0` for an empty trace.
+	pub fn simulate_hit_ratio<I: IntoIterator<Item = K>>(
+		trace: I,
+		cap: usize,
+		sample_size: usize,
+	) -> f64 {
+		let mut cache = Self::new(cap, sample_size);
+		let mut hits = 0usize;
+		let mut accesses = 0usize;
+
+		for k in trace {
+			accesses += 1;
+			if cache.get(&k).is_some() {
+				hits += 1;
+			} else {
+				cache.put(k, ());
+			}
+		}
+
+		if accesses == 0 {
+			return 0.0;
+		}
+
+		hits as f64 / accesses as f64
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::frequency::FrequencyModel;
+	use super::{
+		AccessOutcome, AdmissionCandidate, CacheBuildError, DecayStrategy, Entry, EntryMetadata,
+		EvictReason, FrequencySketch, ResizeFrequencyPolicy, Segment, Stats, WTinyLfuCache,
+		WTinyLfuCacheBuilder, WindowPolicy,
+	};
+	use std::borrow::Borrow;
+	use std::collections::hash_map::{DefaultHasher, RandomState};
+	use std::collections::HashMap;
+	use std::hash::{BuildHasher, BuildHasherDefault, Hash};
+	use std::mem;
+	use std::panic::{self, AssertUnwindSafe};
+	use std::sync::{Arc, Mutex};
+
+	fn iter_keys<K: Hash + Eq + Ord + Copy, V>(cache: &WTinyLfuCache<K, V>) -> Vec<K> {
+		let mut out = cache.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+		out.sort();
+		out
+	}
+
+	#[test]
+	fn store_and_retrieve_items() {
+		let mut cache = WTinyLfuCache::new(2, 10);
+		cache.push(1, "one");
+		cache.push(2, "two");
+		assert_eq!(cache.get(&1), Some(&"one"));
+		assert_eq!(cache.get(&2), Some(&"two"));
+		assert_eq!(&iter_keys(&cache), &[1, 2]);
+	}
+
+	#[test]
+	fn store_retrieve_and_pop_items() {
+		let mut cache = WTinyLfuCache::new(2, 10);
+		cache.push(1, "one");
+		cache.push(2, "two");
+		assert_eq!(cache.get(&1), Some(&"one"));
+		assert_eq!(cache.get(&2), Some(&"two"));
+
+		cache.pop(&1);
+		assert_eq!(cache.get(&1), None);
+		assert_eq!(cache.get(&2), Some(&"two"));
+		assert_eq!(&iter_keys(&cache), &[2]);
+	}
+
+	#[test]
+	fn check_if_lru_is_correct() {
+		let mut cache = WTinyLfuCache::new(500, 10);
+		cache.push(1, "one");
+		cache.push(2, "two");
+		cache.push(3, "three");
+		cache.push(4, "four");
+		cache.push(5, "five");
+		assert_eq!(cache.peek_lru_window(), Some((&1, &"one")));
+		assert_eq!(cache.peek_lru_main(), None);
+
+		cache.get(&1);
+		cache.get(&2);
+		cache.get(&3);
+		cache.get(&4);
+		cache.get(&5);
+		assert_eq!(cache.peek_lru_window(), Some((&1, &"one")));
+		assert_eq!(cache.peek_lru_main(), None);
+
+		cache.get(&3);
+		cache.get(&2);
+		cache.get(&4);
+		cache.get(&1);
+		cache.get(&5);
+		assert_eq!(cache.peek_lru_window(), Some((&3, &"three")));
+		assert_eq!(cache.peek_lru_main(), None);
+	}
+
+	#[test]
+	fn check_if_cap_and_len_are_correct() {
+		let mut cache = WTinyLfuCache::new(3, 10);
+		cache.push(1, "one");
+		cache.push(2, "two");
+		assert_eq!(cache.cap(), 3);
+		assert_eq!(cache.len(), 2);
+
+		cache.get(&1);
+		cache.get(&2);
+		assert_eq!(cache.cap(), 3);
+		assert_eq!(cache.len(), 2);
+
+		cache.push(3, "three");
+		assert_eq!(cache.cap(), 3);
+		assert_eq!(cache.len(), 3);
+
+		cache.get(&3);
+		assert_eq!(cache.cap(), 3);
+		assert_eq!(cache.len(), 3);
+
+		assert_eq!(&iter_keys(&cache), &[1, 2, 3]);
+	}
+
+	#[test]
+	fn clear_cache() {
+		let mut cache = WTinyLfuCache::new(10, 10);
+		cache.push(1, "one");
+		cache.push(2, "two");
+		assert_eq!(cache.get(&1), Some(&"one"));
+		assert_eq!(cache.get(&2), Some(&"two"));
+		assert_eq!(cache.len(), 2);
+		assert_eq!(cache.cap(), 10);
+		assert_eq!(&iter_keys(&cache), &[1, 2]);
+
+		cache.clear();
+		assert_eq!(cache.get(&1), None);
+		assert_eq!(cache.get(&2), None);
+		assert_eq!(cache.len(), 0);
+		assert_eq!(cache.cap(), 10);
+		assert_eq!(&iter_keys(&cache), &[]);
+	}
+
+	#[test]
+	fn cache_is_send_and_sync() {
+		fn assert_send_sync<T: Send + Sync>() {}
+
+		assert_send_sync::<WTinyLfuCache<u64, String>>();
+		assert_send_sync::<WTinyLfuCache<String, Vec<u8>>>();
+	}
+
+	#[test]
+	fn approx_working_set_tracks_reused_keys() {
+		let mut cache = WTinyLfuCache::new(100, 1000);
+		for i in 0..20 {
+			cache.put(i, i);
+		}
+
+		for i in 0..10 {
+			cache.get(&i);
+			cache.get(&i);
+			cache.get(&i);
+		}
+		for i in 10..20 {
+			cache.get(&i);
+		}
+
+		assert!((10..=12).contains(&cache.approx_working_set()));
+	}
+
+	#[test]
+	fn approx_working_set_counts_keys_hot_in_earlier_samples() {
+		let mut cache = WTinyLfuCache::new(100, 50);
+		for i in 0..5 {
+			cache.put(i, i);
+		}
+		for _ in 0..4 {
+			for i in 0..5 {
+				cache.get(&i);
+			}
+		}
+		// Completes the sample of 50 counted accesses, the halved counts of the keys stay nonzero.
+		for _ in 0..35 {
+			cache.get(&0);
+		}
+		assert_eq!(cache.approx_working_set(), 0);
+		assert!((0..5).all(|i| cache.frequency.estimate(&i) > 0));
+
+		for i in 0..5 {
+			cache.get(&i);
+			cache.get(&i);
+		}
+		assert_eq!(cache.approx_working_set(), 5);
+	}
+
+	#[test]
+	fn put_with_calls_on_replace_only_on_update() {
+		let mut cache = WTinyLfuCache::new(10, 10);
+		let mut replaced = Vec::new();
+
+		cache.put_with(1, "one", |v| replaced.push(v));
+		assert!(replaced.is_empty());
+
+		cache.put_with(1, "uno", |v| replaced.push(v));
+		assert_eq!(&replaced, &["one"]);
+		assert_eq!(cache.peek(&1), Some(&"uno"));
+	}
+
+	#[test]
+	fn sketch_dimensions_scale_with_sample_size() {
+		let small: WTinyLfuCache<u64, u64> = WTinyLfuCache::new(10, 100);
+		let large: WTinyLfuCache<u64, u64> = WTinyLfuCache::new(10, 10_000);
+
+		let (small_width, small_depth) = small.sketch_dimensions();
+		let (large_width, large_depth) = large.sketch_dimensions();
+		assert!(small_width >= 100);
+		assert!(large_width >= 10_000);
+		assert!(large_width > small_width);
+		assert_eq!(small_depth, large_depth);
+	}
+
+	#[test]
+	fn freeze_and_thaw() {
+		let mut cache = WTinyLfuCache::new(10, 10);
+		cache.put(1, "one");
+		cache.put(2, "two");
+		cache.get(&1);
+		cache.get(&1);
+		let frequency = cache.estimate_frequency(&1);
+
+		let frozen = cache.freeze();
+		assert_eq!(frozen.peek(&1), Some(&"one"));
+		assert_eq!(frozen.peek(&3), None);
+		assert!(frozen.contains(&2));
+		assert_eq!(frozen.len(), 2);
+		assert_eq!(frozen.cap(), 10);
+		assert_eq!(frozen.estimate_frequency(&1), frequency);
+		assert_eq!(frozen.iter().count(), 2);
+
+		let mut cache = frozen.thaw();
+		cache.put(3, "three");
+		assert_eq!(&iter_keys(&cache), &[1, 2, 3]);
+	}
+
+	#[test]
+	fn resize_redistributes_window_overflow() {
+		let mut cache = WTinyLfuCache::new(1000, 100);
+		for i in 0..10 {
+			cache.put(i, i);
+		}
+		assert_eq!(cache.window_cache.len(), 10);
+		assert_eq!(cache.main_cache.len(), 0);
+
+		cache.resize(200);
+		assert!(cache.window_cache.len() <= cache.window_cache.cap().get());
+		assert_eq!(cache.window_cache.len(), 2);
+		assert_eq!(cache.main_cache.len(), 8);
+		assert_eq!(cache.peek_lru_window(), Some((&8, &8)));
+		assert_eq!(&iter_keys(&cache), &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+	}
+
+	#[test]
+	fn observe_weighted_matches_repeated_observe() {
+		let mut repeated: WTinyLfuCache<i32, i32> = WTinyLfuCache::new(10, 4);
+		let mut batched: WTinyLfuCache<i32, i32> = WTinyLfuCache::new(10, 4);
+
+		repeated.observe(&1);
+		repeated.observe(&1);
+		batched.observe(&1);
+		batched.observe(&1);
+
+		// Crosses a sample reset in the middle of the batch.
+		for _ in 0..5 {
+			repeated.observe(&1);
+		}
+		batched.observe_weighted(&1, 5);
+
+		assert_eq!(
+			repeated.estimate_frequency(&1),
+			batched.estimate_frequency(&1)
+		);
+		assert_eq!(batched.estimate_frequency(&1), 4);
+		assert_eq!(repeated.approx_working_set(), batched.approx_working_set());
+	}
+
+	#[test]
+	fn simulated_hit_ratio_increases_with_capacity() {
+		let trace = || (0..2000).map(|i| (i * 7919) % 50);
+
+		let small = WTinyLfuCache::simulate_hit_ratio(trace(), 5, 100);
+		let medium = WTinyLfuCache::simulate_hit_ratio(trace(), 25, 100);
+		let large = WTinyLfuCache::simulate_hit_ratio(trace(), 100, 100);
+		assert!(small < medium);
+		assert!(medium < large);
+		assert!(large > 0.9);
+		assert_eq!(
+			WTinyLfuCache::<i32, ()>::simulate_hit_ratio(Vec::new(), 10, 10),
+			0.0
+		);
+	}
+
+	#[test]
+	fn put_if_present_only_updates_resident_keys() {
+		let mut cache = WTinyLfuCache::new(10, 10);
+		cache.put(1, "one");
+
+		assert_eq!(cache.put_if_present(&1, "uno"), Some("one"));
+		assert_eq!(cache.peek(&1), Some(&"uno"));
+
+		assert_eq!(cache.put_if_present(&2, "two"), None);
+		assert!(!cache.contains(&2));
+		assert_eq!(cache.len(), 1);
+
+		// Pushes the first key out of the window into the main cache.
+		cache.put(3, "three");
+		assert_eq!(cache.put_if_present(&1, "eins"), Some("uno"));
+		assert_eq!(cache.peek(&1), Some(&"eins"));
+
+		// Both updates were counted like updates with `put`, but neither as a lookup nor as a promotion.
+		assert_eq!(cache.estimate_frequency(&1), 2);
+		assert!(!cache.is_protected(&1));
+		assert_eq!(cache.stats().hits, 0);
+		assert_eq!(cache.stats().misses, 0);
+	}
+
+	#[test]
+	fn stats_break_down_hits_by_segment() {
+		let mut cache = WTinyLfuCache::new(100, 100);
+		for i in 0..10 {
+			cache.put(i, i);
+		}
+
+		// Only the last key is still in the window, the others were admitted into the main cache.
+		for _ in 0..3 {
+			for i in 0..10 {
+				cache.get(&i);
+			}
+		}
+		cache.get(&10);
+
+		let stats = cache.stats();
+		assert_eq!(stats.hits, 30);
+		assert_eq!(stats.misses, 1);
+		assert_eq!(stats.window_hits, 3);
+		assert_eq!(stats.main_hits, 27);
+		assert_eq!(stats.window_hit_ratio(), 3.0 / 31.0);
+		assert_eq!(stats.main_hit_ratio(), 27.0 / 31.0);
+	}
+
+	#[test]
+	fn sample_entries_returns_at_most_n_resident_entries() {
+		let mut cache = WTinyLfuCache::new(100, 10);
+		for i in 0..5 {
+			cache.put(i, i * 10);
+		}
+
+		let sample = cache.sample_entries(3);
+		assert_eq!(sample.len(), 3);
+		for (k, v) in sample {
+			assert_eq!(cache.peek(k), Some(v));
+		}
+
+		assert_eq!(cache.sample_entries(10).len(), 5);
+		assert!(cache.sample_entries(0).is_empty());
+	}
+
+	#[test]
+	fn on_evict_receives_eviction_reason() {
+		let evicted = Arc::new(Mutex::new(Vec::new()));
+		let mut cache = WTinyLfuCache::new(100, 100);
+		{
+			let evicted = Arc::clone(&evicted);
+			cache.set_on_evict(move |k, _, reason| evicted.lock().unwrap().push((k, reason)));
+		}
+
+		// Fills the window and the probationary segment.
+		for i in 0..20 {
+			cache.put(i, i);
+		}
+		assert!(evicted.lock().unwrap().is_empty());
+
+		// The window cache victim is as cold as the main cache victim, so it isn't admitted.
+		cache.put(20, 20);
+		assert_eq!(
+			evicted.lock().unwrap().pop(),
+			Some((19, EvictReason::Rejected))
+		);
+
+		// The window cache victim is warmer than the main cache victim, so the latter is evicted.
+		cache.get(&20);
+		cache.get(&20);
+		cache.put(21, 21);
+		assert_eq!(
+			evicted.lock().unwrap().pop(),
+			Some((0, EvictReason::Capacity))
+		);
+
+		// Entries returned to the caller aren't passed to the callback.
+		assert!(cache.push(22, 22).is_some());
+		cache.put(22, 23);
+		assert!(evicted.lock().unwrap().is_empty());
+
+		cache.resize(10);
+		let len = cache.len();
+		assert!(len < 20);
+		assert_eq!(evicted.lock().unwrap().len(), 20 - len);
+		assert!(evicted
+			.lock()
+			.unwrap()
+			.drain(..)
+			.all(|(_, reason)| reason == EvictReason::Resize));
+
+		cache.clear();
+		assert_eq!(evicted.lock().unwrap().len(), len);
+		assert!(evicted
+			.lock()
+			.unwrap()
+			.iter()
+			.all(|&(_, reason)| reason == EvictReason::Clear));
+	}
+
+	#[test]
+	fn is_protected_after_promotion() {
+		let mut cache = WTinyLfuCache::new(100, 10);
+		cache.put(1, "one");
+		assert!(!cache.is_protected(&1));
+
+		// Pushes the first key out of the window into the probationary segment.
+		cache.put(2, "two");
+		assert!(cache.contains(&1));
+		assert!(!cache.is_protected(&1));
+
+		cache.get(&1);
+		assert!(cache.is_protected(&1));
+		assert!(!cache.is_protected(&2));
+	}
+
+	#[test]
+	fn window_policies_admit_into_main() {
+		for policy in [WindowPolicy::Lru, WindowPolicy::Fifo, WindowPolicy::Sieve] {
+			let mut cache = WTinyLfuCache::with_window_policy(200, 100, policy);
+			cache.put(1, "one");
+			cache.put(2, "two");
+			cache.put(3, "three");
+			assert_eq!(cache.window_cache.len(), 2);
+			assert_eq!(cache.main_cache.len(), 1);
+			assert_eq!(cache.get(&1), Some(&"one"));
+			assert_eq!(cache.get(&3), Some(&"three"));
+			assert_eq!(cache.put(3, "drei"), Some("three"));
+			assert_eq!(&iter_keys(&cache), &[1, 2, 3]);
+
+			cache.put(4, "four");
+			assert_eq!(cache.len(), 4);
+			assert!(cache.is_protected(&1));
+		}
+	}
+
+	#[test]
+	fn get_observed_reports_outcome() {
+		let mut cache = WTinyLfuCache::new(100, 100);
+		cache.put(1, "one");
+		cache.put(2, "two");
+
+		let mut outcome = None;
+		assert_eq!(cache.get_observed(&1, |o| outcome = Some(o)), Some(&"one"));
+		assert_eq!(
+			outcome,
+			Some(AccessOutcome::Hit {
+				segment: Segment::Probationary,
+				frequency: 1,
+			})
+		);
+
+		assert_eq!(cache.get_observed(&1, |o| outcome = Some(o)), Some(&"one"));
+		assert_eq!(
+			outcome,
+			Some(AccessOutcome::Hit {
+				segment: Segment::Protected,
+				frequency: 2,
+			})
+		);
+
+		cache.get_observed(&2, |o| outcome = Some(o));
+		assert!(matches!(
+			outcome,
+			Some(AccessOutcome::Hit {
+				segment: Segment::Window,
+				..
+			})
+		));
+
+		assert_eq!(cache.get_observed(&3, |o| outcome = Some(o)), None);
+		assert_eq!(outcome, Some(AccessOutcome::Miss));
+		assert_eq!(cache.stats().hits, 3);
+		assert_eq!(cache.stats().misses, 1);
+	}
+
+	#[test]
+	fn get_cloned_all_keeps_input_order() {
+		let mut cache = WTinyLfuCache::new(100, 100);
+		cache.put(1, String::from("one"));
+		cache.put(2, String::from("two"));
+
+		assert_eq!(
+			cache.get_cloned_all(&[2, 3, 1]),
+			vec![Some(String::from("two")), None, Some(String::from("one"))]
+		);
+		assert_eq!(cache.estimate_frequency(&1), 1);
+		assert_eq!(cache.estimate_frequency(&3), 0);
+	}
+
+	#[test]
+	#[should_panic(expected = "entry size must be at least 1 byte")]
+	fn with_memory_budget_rejects_zero_entry_size() {
+		let _: WTinyLfuCache<u64, u64> = WTinyLfuCache::with_memory_budget(1 << 20, 0, 1000);
+	}
+
+	#[test]
+	fn with_memory_budget_accounts_for_overhead() {
+		let overhead = FrequencyModel::<u64>::memory_overhead(1000);
+		let cache: WTinyLfuCache<u64, [u8; 48]> =
+			WTinyLfuCache::with_memory_budget(overhead + 64 * 1000 + 63, 64, 1000);
+		assert_eq!(cache.cap(), 1000);
+
+		let mut cache: WTinyLfuCache<u64, [u8; 48]> =
+			WTinyLfuCache::with_memory_budget(1 << 20, 64, 1000);
+		for i in 0..10_000 {
+			cache.put(i, [0; 48]);
+		}
+		assert!(cache.len() * 64 + overhead <= 1 << 20);
+
+		let cache: WTinyLfuCache<u64, u64> = WTinyLfuCache::with_memory_budget(16, 64, 1000);
+		assert_eq!(cache.cap(), 1);
+	}
+
+	#[test]
+	fn retain_and_forget_drops_removed_frequencies() {
+		let mut cache = WTinyLfuCache::new(100, 1000);
+		for i in 0..10 {
+			cache.put(i, i);
+		}
+		for i in 0..10 {
+			for _ in 0..3 {
+				cache.get(&i);
+			}
+		}
+		assert!(cache.estimate_frequency(&1) >= 3);
+
+		cache.retain_and_forget(|k, _| k % 2 == 0);
+
+		assert_eq!(cache.len(), 5);
+		for i in 0..10 {
+			assert_eq!(cache.contains(&i), i % 2 == 0);
+			if i % 2 == 0 {
+				assert!(cache.estimate_frequency(&i) >= 3);
+			}
+		}
+		// Survivors are re-recorded, so removed keys can only be estimated through collisions.
+		assert!((1..10)
+			.step_by(2)
+			.any(|i| cache.estimate_frequency(&i) == 0));
+	}
+
+	#[cfg(feature = "global-lru")]
+	#[test]
+	fn peek_lru_global_spans_segments() {
+		let mut cache = WTinyLfuCache::new(100, 1000);
+		for i in 0..10 {
+			cache.put(i, i);
+		}
+		assert_eq!(cache.peek_lru_global(), Some((&0, &0)));
+
+		// 0 and 1 end up in the protected segment, 2 in the probationary segment and 9 in the window cache.
+		cache.get(&0);
+		cache.get(&1);
+		cache.get(&9);
+		assert!(cache.is_protected(&0));
+		assert_eq!(cache.peek_lru_global(), Some((&2, &2)));
+
+		for i in 2..9 {
+			cache.get(&i);
+		}
+		assert_eq!(cache.peek_lru_global(), Some((&0, &0)));
+
+		cache.put(0, 10);
+		assert_eq!(cache.peek_lru_global(), Some((&1, &1)));
+		cache.peek(&1);
+		cache.pop(&1);
+		assert_eq!(cache.peek_lru_global(), Some((&9, &9)));
+	}
+
+	#[test]
+	fn import_frequency_copies_estimates() {
+		let mut source = WTinyLfuCache::new(100, 1000);
+		for i in 0..20 {
+			source.put(i, i);
+			for _ in 0..i % 5 {
+				source.get(&i);
+			}
+		}
+		source.observe_weighted(&100, 3);
+
+		let mut fresh: WTinyLfuCache<i32, i32> = WTinyLfuCache::new(100, 1000);
+		fresh.import_frequency(source.clone_frequency_model());
+		assert!(fresh.is_empty());
+		for i in (0..20).chain([100, 200]) {
+			assert_eq!(fresh.estimate_frequency(&i), source.estimate_frequency(&i));
+		}
+		assert_eq!(fresh.sketch_dimensions(), source.sketch_dimensions());
+
+		// The copy is independent of the source.
+		fresh.observe(&200);
+		assert_eq!(source.estimate_frequency(&200), 0);
+	}
+
+	#[test]
+	fn push_keeps_segments_within_capacity() {
+		// The capacity invariant is checked by debug assertions inside `push` and the promotion path.
+		for cap in [1, 2, 3, 4, 5, 10, 100] {
+			let mut cache = WTinyLfuCache::new(cap, 10);
+			for i in 0..50 {
+				cache.push(i, i);
+				cache.get(&i);
+				cache.get(&(i / 2));
+				cache.push(i / 3, i);
+			}
+
+			if cap >= 3 {
+				assert!(cache.len() <= cap);
+			}
+		}
+	}
+
+	#[test]
+	fn into_sorted_by_frequency_desc_orders_by_estimate() {
+		let mut cache = WTinyLfuCache::new(100, 1000);
+		for i in 0..20 {
+			cache.put(i, i * 10);
+		}
+		for _ in 0..5 {
+			cache.get(&7);
+		}
+		for _ in 0..3 {
+			cache.get(&3);
+		}
+
+		let entries = cache.into_sorted_by_frequency_desc();
+		assert_eq!(entries.len(), 20);
+		assert_eq!(entries[0].0, 7);
+		assert_eq!(entries[1].0, 3);
+		assert!(entries.windows(2).all(|pair| pair[0].2 >= pair[1].2));
+
+		let mut keys: Vec<i32> = entries
+			.iter()
+			.map(|(k, v, _)| {
+				assert_eq!(*v, k * 10);
+				*k
+			})
+			.collect();
+		keys.sort();
+		assert_eq!(keys, (0..20).collect::<Vec<_>>());
+	}
+
+	#[test]
+	fn get_window_ignores_main_cache() {
+		let mut cache = WTinyLfuCache::new(200, 1000);
+		cache.put(1, "one");
+		cache.put(2, "two");
+		cache.put(3, "three");
+		assert_eq!(cache.peek_lru_main(), Some((&1, &"one")));
+		assert_eq!(cache.peek_lru_window(), Some((&2, &"two")));
+
+		assert_eq!(cache.get_window(&1), None);
+		assert_eq!(cache.estimate_frequency(&1), 0);
+
+		*cache.get_window(&2).unwrap() = "deux";
+		assert_eq!(cache.peek_lru_window(), Some((&3, &"three")));
+		assert_eq!(cache.peek(&2), Some(&"deux"));
+		assert_eq!(cache.estimate_frequency(&2), 1);
+		assert_eq!(cache.stats().hits + cache.stats().misses, 0);
+	}
+
+	#[test]
+	fn resize_sketch_keeps_hot_keys_hot() {
+		let mut cache = WTinyLfuCache::new(100, 100);
+		for i in 0..10 {
+			cache.put(i, i);
+		}
+		for _ in 0..5 {
+			cache.get(&1);
+			cache.get(&2);
+		}
+
+		cache.resize_sketch(10_000);
+		assert_eq!(cache.sketch_dimensions().0, 16_384);
+		let cold = (3..10).map(|i| cache.estimate_frequency(&i)).max().unwrap();
+		assert!(cache.estimate_frequency(&1) >= 5);
+		assert!(cache.estimate_frequency(&2) >= 5);
+		assert!(cold < 5);
+	}
+
+	#[test]
+	fn try_new_rejects_huge_sample_sizes() {
+		let result: Result<WTinyLfuCache<u64, u64>, _> = WTinyLfuCache::try_new(10, 10_000_000);
+		assert_eq!(
+			result.err(),
+			Some(CacheBuildError::SampleSizeTooLarge {
+				cap: 10,
+				sample_size: 10_000_000
+			})
+		);
+
+		let cache: WTinyLfuCache<u64, u64> = WTinyLfuCache::try_new(10, 80).unwrap();
+		assert_eq!(cache.cap(), 10);
+
+		assert!(WTinyLfuCache::<u64, u64>::try_with_max_sample_size_ratio(10, 200, 20).is_ok());
+		assert!(WTinyLfuCache::<u64, u64>::try_with_max_sample_size_ratio(10, 201, 20).is_err());
+	}
+
+	#[cfg(feature = "rand")]
+	#[test]
+	fn peek_random_returns_every_pair() {
+		use rand::rngs::SmallRng;
+		use rand::SeedableRng;
+
+		let mut rng = SmallRng::seed_from_u64(42);
+		let mut cache = WTinyLfuCache::new(100, 1000);
+		assert_eq!(cache.peek_random(&mut rng), None);
+
+		for i in 0..20 {
+			cache.put(i, i);
+			cache.get(&(i / 2));
+		}
+
+		let mut seen = [false; 20];
+		for _ in 0..1000 {
+			let (k, v) = cache.peek_random(&mut rng).unwrap();
+			assert_eq!(k, v);
+			seen[*k as usize] = true;
+		}
+		assert!(seen.iter().all(|seen| *seen));
+	}
+
+	#[test]
+	fn evict_lru_batch_evicts_protected_pairs_last() {
+		let mut cache = WTinyLfuCache::new(200, 1000);
+		for i in 0..10 {
+			cache.put(i, i);
+		}
+		// 0 is promoted to the protected segment, 8 and 9 stay in the window cache.
+		cache.get(&0);
+
+		let first = cache.evict_lru_batch(4);
+		assert_eq!(first, vec![(1, 1), (2, 2), (3, 3), (4, 4)]);
+		assert_eq!(cache.len(), 6);
+
+		let rest = cache.evict_lru_batch(100);
+		assert_eq!(rest, vec![(5, 5), (6, 6), (7, 7), (8, 8), (9, 9), (0, 0)]);
+		assert!(cache.is_empty());
+		assert!(cache.evict_lru_batch(1).is_empty());
+	}
+
+	#[test]
+	fn promote_demotes_protected_overflow() {
+		// The probationary and the protected segments can hold one pair each.
+		let mut cache = WTinyLfuCache::new(3, 10);
+		cache.put(1, "one");
+		cache.put(2, "two");
+		assert!(cache.promote(&1));
+		assert!(cache.is_protected(&1));
+
+		cache.put(3, "three");
+		assert!(cache.promote(&2));
+		assert!(cache.is_protected(&2));
+		assert!(!cache.is_protected(&1));
+		assert!(cache.contains(&1));
+
+		assert!(!cache.promote(&2));
+		assert!(!cache.promote(&3));
+		assert!(!cache.promote(&4));
+		assert_eq!(cache.estimate_frequency(&2), 0);
+	}
+
+	#[test]
+	fn recommended_sample_size_scales_with_cap() {
+		type Cache = WTinyLfuCache<u64, u64>;
+		assert_eq!(Cache::recommended_sample_size(0), 10);
+		assert_eq!(Cache::recommended_sample_size(1), 10);
+		assert_eq!(Cache::recommended_sample_size(100), 1000);
+		assert_eq!(Cache::recommended_sample_size(usize::MAX), usize::MAX);
+
+		for cap in [1, 2, 10, 1000] {
+			assert!(Cache::try_new(cap, Cache::recommended_sample_size(cap)).is_ok());
+		}
+	}
+
+	#[test]
+	fn lru_rank_reflects_recency() {
+		let mut cache = WTinyLfuCache::new(200, 1000);
+		for i in 0..8 {
+			cache.put(i, i);
+		}
+		// 0 to 5 are stored in the probationary segment and 6 and 7 in the window cache.
+		assert_eq!(cache.lru_rank(&0), Some((5, 6)));
+		assert_eq!(cache.lru_rank(&5), Some((0, 6)));
+		assert_eq!(cache.lru_rank(&6), Some((1, 2)));
+
+		cache.get(&6);
+		assert_eq!(cache.lru_rank(&6), Some((0, 2)));
+		assert_eq!(cache.lru_rank(&7), Some((1, 2)));
+
+		cache.get(&0);
+		cache.get(&3);
+		assert_eq!(cache.lru_rank(&3), Some((0, 2)));
+		assert_eq!(cache.lru_rank(&0), Some((1, 2)));
+		assert_eq!(cache.lru_rank(&1), Some((3, 4)));
+		assert_eq!(cache.lru_rank(&8), None);
+	}
+
+	#[test]
+	fn put_hashes_once_per_segment_probed() {
+		use std::cell::Cell;
+		use std::hash::Hasher;
+
+		thread_local! {
+			static HASHES: Cell<usize> = const { Cell::new(0) };
+		}
+
+		#[derive(PartialEq, Eq)]
+		struct CountingKey(u64);
+
+		impl Hash for CountingKey {
+			fn hash<H: Hasher>(&self, state: &mut H) {
+				HASHES.with(|hashes| hashes.set(hashes.get() + 1));
+				self.0.hash(state);
+			}
+		}
+
+		fn hashes_during<F: FnOnce()>(f: F) -> usize {
+			let before = HASHES.with(Cell::get);
+			f();
+			HASHES.with(Cell::get) - before
+		}
+
+		let mut cache = WTinyLfuCache::new(200, 1000);
+		for i in 0..4 {
+			cache.put(CountingKey(i), i);
+		}
+		cache.get(&CountingKey(0));
+		assert!(cache.is_protected(&CountingKey(0)));
+		// Makes room in the window cache, so the insert below doesn't evict anything.
+		cache.pop(&CountingKey(3));
+
+		// Probes the window and both main cache segments, then inserts into the window, which hashes twice
+		// inside `LruCache::push`.
+		assert_eq!(
+			hashes_during(|| assert_eq!(cache.put(CountingKey(4), 4), None)),
+			5
+		);
+		// Updates in the window, the probationary and the protected segment. Each update also records an access,
+		// which hashes the key in the frequency model, measured here on a copy of the model.
+		let recording = |cache: &WTinyLfuCache<CountingKey, u64>, k: CountingKey| {
+			let mut frequency = cache.clone_frequency_model();
+			hashes_during(|| frequency.increment(&k))
+		};
+		let expected = 1 + recording(&cache, CountingKey(4));
+		assert_eq!(
+			hashes_during(|| assert_eq!(cache.put(CountingKey(4), 5), Some(4))),
+			expected
+		);
+		let expected = 2 + recording(&cache, CountingKey(1));
+		assert_eq!(
+			hashes_during(|| assert_eq!(cache.put(CountingKey(1), 6), Some(1))),
+			expected
+		);
+		let expected = 3 + recording(&cache, CountingKey(0));
+		assert_eq!(
+			hashes_during(|| assert_eq!(cache.put(CountingKey(0), 7), Some(0))),
+			expected
+		);
+		let expected = 3 + recording(&cache, CountingKey(0));
+		assert_eq!(
+			hashes_during(|| assert_eq!(cache.push(CountingKey(0), 8).map(|(_, v)| v), Some(7))),
+			expected
+		);
+	}
+
+	#[test]
+	fn decay_frequency_scales_estimates() {
+		let mut cache: WTinyLfuCache<i32, i32> = WTinyLfuCache::new(100, 1000);
+		cache.observe_weighted(&1, 11);
+		cache.observe_weighted(&2, 5);
+
+		let hot = cache.estimate_frequency(&1);
+		let warm = cache.estimate_frequency(&2);
+		assert!(hot > warm);
+
+		cache.decay_frequency(0.5);
+		// The doorkeeper still contributes one to each estimate.
+		assert_eq!(cache.estimate_frequency(&1), (hot - 1) / 2 + 1);
+		assert_eq!(cache.estimate_frequency(&2), (warm - 1) / 2 + 1);
+		assert!(cache.estimate_frequency(&1) > cache.estimate_frequency(&2));
+	}
+
+	#[test]
+	fn get_or_insert_with_evicting_returns_victim() {
+		for policy in [WindowPolicy::Lru, WindowPolicy::Fifo, WindowPolicy::Sieve] {
+			let mut cache = WTinyLfuCache::with_window_policy(3, 10, policy);
+			cache.put(1, 10);
+			cache.put(2, 20);
+			cache.promote(&1);
+			cache.put(3, 30);
+			assert_eq!(cache.len(), 3);
+
+			// 3 is evicted from the window cache and loses the admission contest against 2.
+			let (v, evicted) = cache.get_or_insert_with_evicting(4, || 40);
+			assert_eq!(*v, 40);
+			*v += 1;
+			assert_eq!(evicted, Some((3, 30)));
+			assert_eq!(cache.peek(&4), Some(&41));
+
+			let (v, evicted) = cache.get_or_insert_with_evicting(4, || unreachable!());
+			assert_eq!(*v, 41);
+			assert_eq!(evicted, None);
+			assert_eq!(cache.stats().hits, 1);
+			assert_eq!(cache.stats().misses, 1);
+		}
+	}
+
+	#[test]
+	fn grow_to_never_evicts() {
+		let mut cache = WTinyLfuCache::new(1000, 1000);
+		for i in 0..208 {
+			cache.put(i, i);
+		}
+		for i in 0..150 {
+			cache.get(&i);
+		}
+		for _ in 0..9 {
+			cache.pop_lru_window();
+		}
+		// 150 protected, 48 probationary and 1 window pair.
+		assert_eq!(cache.len(), 199);
+
+		let evictions = Arc::new(Mutex::new(0));
+		let counter = Arc::clone(&evictions);
+		cache.set_on_evict(move |_, _, _| *counter.lock().unwrap() += 1);
+
+		// The probationary segment only gets a fifth of the main cache, so holding 48 pairs takes a capacity
+		// of 242 rather than 199.
+		cache.grow_to(10);
+		assert_eq!(cache.cap(), 242);
+		assert_eq!(cache.len(), 199);
+		assert_eq!(*evictions.lock().unwrap(), 0);
+
+		cache.grow_to(2000);
+		assert_eq!(cache.cap(), 2000);
+		assert_eq!(cache.len(), 199);
+		assert_eq!(*evictions.lock().unwrap(), 0);
+	}
+
+	#[test]
+	fn iter_by_segment_groups_entries() {
+		let mut cache = WTinyLfuCache::new(200, 1000);
+		for i in 0..5 {
+			cache.put(i, i * 10);
+		}
+		cache.get(&0);
+		cache.get(&2);
+
+		let entries: Vec<_> = cache
+			.iter_by_segment()
+			.map(|(segment, k, v)| (segment, *k, *v))
+			.collect();
+		assert_eq!(
+			entries,
+			vec![
+				(Segment::Window, 4, 40),
+				(Segment::Window, 3, 30),
+				(Segment::Probationary, 1, 10),
+				(Segment::Protected, 2, 20),
+				(Segment::Protected, 0, 0),
+			]
+		);
+	}
+
+	#[test]
+	fn contains_mask_matches_contains() {
+		let mut cache = WTinyLfuCache::new(100, 1000);
+		for i in (0..10).step_by(3) {
+			cache.put(i, i);
+		}
+
+		let keys: Vec<i32> = (0..10).collect();
+		let mask = cache.contains_mask(&keys);
+		assert_eq!(
+			mask,
+			[true, false, false, true, false, false, true, false, false, true]
+		);
+		assert!(keys
+			.iter()
+			.zip(mask)
+			.all(|(k, stored)| cache.contains(k) == stored));
+		assert!(cache.contains_mask([]).is_empty());
+	}
+
+	#[test]
+	fn set_protected_ratio_splits_main_cache() {
+		let mut cache = WTinyLfuCache::new(200, 100);
+		// The window holds 2 pairs and the main cache 198.
+		assert_eq!(cache.main_cache.segment_caps(), (39, 159));
+
+		cache.set_protected_ratio(0.5);
+		assert_eq!(cache.main_cache.segment_caps(), (99, 99));
+		assert_eq!(cache.cap(), 200);
+
+		for i in 0..3 {
+			cache.put(i, i);
+		}
+		cache.pop_lru_window();
+		assert!(!cache.is_protected(&0));
+		assert_eq!(cache.get(&0), Some(&0));
+		assert!(cache.is_protected(&0));
+
+		// The resized main cache keeps its split.
+		cache.resize(1000);
+		assert_eq!(cache.main_cache.segment_caps(), (495, 495));
+	}
+
+	#[test]
+	fn replace_counts_as_access() {
+		let mut cache = WTinyLfuCache::new(100, 10);
+		cache.put(1, 10);
+		cache.put(2, 20);
+		cache.pop_lru_window();
+		assert!(!cache.is_protected(&1));
+		let frequency = cache.estimate_frequency(&1);
+
+		assert_eq!(cache.replace(&1, 11), Some(10));
+		assert_eq!(cache.peek(&1), Some(&11));
+		assert_eq!(cache.estimate_frequency(&1), frequency + 1);
+		assert!(cache.is_protected(&1));
+		assert_eq!(cache.stats().hits, 1);
+
+		assert_eq!(cache.replace(&3, 30), None);
+		assert!(!cache.contains(&3));
+	}
+
+	#[test]
+	fn prune_cold_window_keeps_warm_pairs() {
+		for policy in [WindowPolicy::Lru, WindowPolicy::Fifo, WindowPolicy::Sieve] {
+			let mut cache = WTinyLfuCache::with_window_policy(1000, 100, policy);
+			for i in 0..10 {
+				cache.put(i, i);
+			}
+			for _ in 0..3 {
+				cache.get(&2);
+				cache.get(&7);
+			}
+
+			let pruned = cache.prune_cold_window(2);
+			let pruned_keys = pruned.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+			assert_eq!(&pruned_keys, &[0, 1, 3, 4, 5, 6, 8, 9]);
+			assert_eq!(cache.window_cache.len(), 2);
+			assert!(cache.contains(&2));
+			assert!(cache.contains(&7));
+		}
+	}
+
+	#[test]
+	fn get_or_insert_with_evicting_survives_panicking_closure() {
+		let mut cache = WTinyLfuCache::new(100, 10);
+		cache.put(1, 1);
+
+		let result = panic::catch_unwind(AssertUnwindSafe(|| {
+			cache.get_or_insert_with_evicting(2, || panic!("value can't be computed"));
+		}));
+		assert!(result.is_err());
+		assert_eq!(cache.len(), 1);
+		assert!(!cache.contains(&2));
+		assert_eq!(cache.stats().misses, 0);
+
+		assert_eq!(*cache.get_or_insert_with_evicting(2, || 2).0, 2);
+		assert_eq!(cache.len(), 2);
+	}
+
+	#[test]
+	fn set_window_capacity_splits_exactly() {
+		let mut cache = WTinyLfuCache::new(1000, 100);
+		for i in 0..10 {
+			cache.put(i, i);
+		}
+
+		cache.set_window_capacity(256);
+		assert_eq!(cache.window_cache.cap().get(), 256);
+		assert_eq!(cache.main_cache.cap(), 744);
+		assert_eq!(cache.cap(), 1000);
+		assert_eq!(cache.len(), 10);
+
+		cache.set_window_capacity(1);
+		assert_eq!(cache.window_cache.cap().get(), 1);
+		assert_eq!(cache.main_cache.cap(), 999);
+		assert_eq!(cache.window_cache.len(), 1);
+		assert_eq!(cache.len(), 10);
+
+		cache.resize(1000);
+		assert_eq!(cache.window_cache.cap().get(), 10);
+	}
+
+	#[test]
+	fn grow_to_keeps_pairs_after_set_window_capacity() {
+		let mut cache = WTinyLfuCache::new(100, 100);
+		cache.set_window_capacity(50);
+		for i in 0..60 {
+			cache.put(i, i);
+		}
+		assert_eq!(cache.len(), 60);
+		assert_eq!(cache.window_cache.len(), 50);
+
+		cache.grow_to(100);
+		assert_eq!(cache.len(), 60);
+		assert_eq!(cache.window_cache.len(), 50);
+		assert!(cache.window_cache.cap().get() >= 50);
+		assert!((0..60).all(|i| cache.contains(&i)));
+	}
+
+	#[test]
+	fn set_protected_ratio_keeps_window_split() {
+		let mut cache = WTinyLfuCache::new(100, 1000);
+		cache.set_window_capacity(50);
+		cache.set_protected_ratio(0.5);
+		assert_eq!(cache.window_cache.cap().get(), 50);
+		assert_eq!(cache.main_cache.cap(), 50);
+		assert_eq!(cache.main_cache.segment_caps(), (25, 25));
+
+		for i in 0..200 {
+			cache.put(i, i);
+			cache.get(&i);
+		}
+		assert!(cache.len() <= cache.cap());
+	}
+
+	#[test]
+	#[should_panic(expected = "window capacity must be at least 1")]
+	fn set_window_capacity_rejects_huge_capacity() {
+		let mut cache: WTinyLfuCache<u64, u64> = WTinyLfuCache::new(100, 1000);
+		cache.set_window_capacity(usize::MAX);
+	}
+
+	#[test]
+	fn with_entries_inserts_every_entry() {
+		let cache = WTinyLfuCache::with_entries(100, 10, [(1, "one"), (2, "two"), (3, "three")]);
+		assert_eq!(cache.len(), 3);
+		assert_eq!(cache.cap(), 100);
+		assert_eq!(cache.peek(&1), Some(&"one"));
+		assert_eq!(cache.peek(&2), Some(&"two"));
+		assert_eq!(cache.peek(&3), Some(&"three"));
+	}
+
+	#[test]
+	fn resize_with_policy_updates_sketch() {
+		for (policy, expected) in [
+			(ResizeFrequencyPolicy::Keep, 8),
+			(ResizeFrequencyPolicy::Decay, 4),
+			(ResizeFrequencyPolicy::Reset, 0),
+		] {
+			let mut cache = WTinyLfuCache::new(100, 1000);
+			cache.put(1, 1);
+			for _ in 0..8 {
+				cache.get(&1);
+			}
+			// The first access only sets the doorkeeper bit, the others increment the sketch.
+			assert_eq!(cache.estimate_frequency(&1), 8);
+
+			cache.resize_with_policy(200, policy);
+			assert_eq!(cache.cap(), 200);
+			assert_eq!(cache.estimate_frequency(&1), expected);
+			assert!(cache.contains(&1));
+		}
+	}
+
+	#[test]
+	fn admission_candidates_match_estimates() {
+		let mut cache = WTinyLfuCache::new(100, 1000);
+		for i in 0..19 {
+			cache.put(i, i);
+		}
+		// The probationary segment holds 18 of its 19 pairs.
+		assert_eq!(cache.admission_candidates(), None);
+
+		cache.put(19, 19);
+		for _ in 0..3 {
+			cache.observe(&0);
+		}
+
+		let (window_cache_victim, main_cache_victim) = cache.admission_candidates().unwrap();
+		assert_eq!(
+			window_cache_victim,
+			AdmissionCandidate {
+				key: &19,
+				frequency: cache.estimate_frequency(&19),
+			}
+		);
+		assert_eq!(
+			main_cache_victim,
+			AdmissionCandidate {
+				key: &0,
+				frequency: cache.estimate_frequency(&0),
+			}
+		);
+		assert_eq!(main_cache_victim.frequency, 3);
+		assert_eq!(window_cache_victim.frequency, 0);
+
+		// The window cache victim loses and is rejected.
+		cache.put(20, 20);
+		assert!(!cache.contains(&19));
+		assert!(cache.contains(&0));
+	}
+
+	#[test]
+	fn look_up_borrowed_keys() {
+		let mut cache: WTinyLfuCache<String, i32> = WTinyLfuCache::new(100, 10);
+		cache.put("one".to_string(), 1);
+		cache.put("two".to_string(), 2);
+		cache.put("three".to_string(), 3);
+
+		assert!(cache.contains("one"));
+		assert_eq!(cache.peek("two"), Some(&2));
+		*cache.peek_mut("two").unwrap() = 22;
+		assert_eq!(cache.get("two"), Some(&22));
+		*cache.get_mut("three").unwrap() += 30;
+		assert_eq!(cache.peek("three"), Some(&33));
+
+		cache.get("one");
+		cache.get("one");
+		assert_eq!(cache.estimate_frequency("one"), 2);
+		assert_eq!(cache.estimate_frequency(&"one".to_string()), 2);
+
+		assert_eq!(cache.pop("one"), Some(1));
+		assert_eq!(cache.pop_entry("two"), Some(("two".to_string(), 22)));
+		assert!(!cache.contains("one"));
+		assert_eq!(cache.len(), 1);
+	}
+
+	#[test]
+	fn other_lookups_accept_borrowed_keys() {
+		let mut cache: WTinyLfuCache<String, i32> = WTinyLfuCache::new(100, 10);
+		cache.put("one".to_string(), 1);
+		cache.put("two".to_string(), 2);
+		cache.put("three".to_string(), 3);
+
+		assert_eq!(cache.get_window("three"), Some(&mut 3));
+		assert_eq!(cache.lru_rank("one"), Some((1, 2)));
+		assert!(cache.promote("one"));
+		assert!(cache.is_protected("one"));
+
+		assert_eq!(cache.put_if_present("two", 22), Some(2));
+		assert_eq!(cache.replace("two", 222), Some(22));
+		assert_eq!(cache.put_if_present("four", 4), None);
+		assert_eq!(cache.get_cloned_all(["one", "four"]), vec![Some(1), None]);
+		assert_eq!(cache.contains_mask(["one", "four"]), vec![true, false]);
+
+		let mut hit = false;
+		cache.get_observed("two", |outcome| {
+			hit = matches!(outcome, AccessOutcome::Hit { .. });
+		});
+		assert!(hit);
+
+		cache.observe("five");
+		cache.observe_weighted("five", 2);
+		assert_eq!(cache.estimate_frequency("five"), 3);
+		assert!(cache.record_miss_hot("five", 4));
+		assert!(cache.protect_key("two"));
+		assert!(cache.is_protected("two"));
+		let [one, missing] = cache.get_disjoint_mut(["one", "four"]);
+		assert_eq!((one, missing), (Some(&mut 1), None));
+		assert_eq!(
+			cache.metadata("two").map(|m| m.segment),
+			Some(Segment::Protected)
+		);
+		*cache.get_mut_dirty("three").unwrap() = 33;
+		assert_eq!(cache.take_dirty(), vec!["three".to_string()]);
+	}
+
+	#[test]
+	fn hash_key_is_consistent() {
+		let cache: WTinyLfuCache<String, i32> = WTinyLfuCache::new(100, 10);
+		let other: WTinyLfuCache<String, i32> = WTinyLfuCache::new(100, 10);
+
+		assert_eq!(cache.hash_key("one"), cache.hash_key(&"one".to_string()));
+		assert_eq!(cache.hash_key("one"), cache.hash_key("one"));
+		assert_ne!(cache.hash_key("one"), cache.hash_key("two"));
+		assert_ne!(cache.hash_key("one"), other.hash_key("one"));
+
+		let hash_builder = BuildHasherDefault::<DefaultHasher>::default();
+		let deterministic: WTinyLfuCache<String, i32, _> =
+			WTinyLfuCache::with_hasher(100, 10, hash_builder.clone());
+		let other_deterministic: WTinyLfuCache<String, i32, _> =
+			WTinyLfuCache::with_hasher(100, 10, hash_builder);
+		assert_eq!(
+			deterministic.hash_key("one"),
+			other_deterministic.hash_key("one")
+		);
+	}
+
+	#[test]
+	fn builder_splits_by_window_ratio() {
+		let mut cache: WTinyLfuCache<i32, i32> = WTinyLfuCacheBuilder::new()
+			.capacity(1000)
+			.window_ratio(0.2)
+			.build()
+			.unwrap();
+		assert_eq!(cache.cap(), 1000);
+		assert_eq!(cache.window_cap(), 200);
+		assert_eq!(cache.main_cap(), 800);
+		assert_eq!(
+			cache.frequency.sample_size(),
+			WTinyLfuCache::<i32, i32>::recommended_sample_size(1000)
+		);
+
+		cache.resize(100);
+		assert_eq!(cache.window_cap(), 20);
+		assert_eq!(cache.main_cap(), 80);
+
+		let default = WTinyLfuCache::<i32, i32>::new(1000, 100);
+		assert_eq!(default.window_cap(), 10);
+		assert_eq!(default.main_cap(), 990);
+
+		for window_ratio in [0.0, 1.0, -0.5, f64::NAN] {
+			let result = WTinyLfuCacheBuilder::new()
+				.capacity(1000)
+				.window_ratio(window_ratio)
+				.build::<i32, i32>();
+			assert_eq!(result.err(), Some(CacheBuildError::InvalidWindowRatio));
+		}
+		assert_eq!(
+			WTinyLfuCacheBuilder::new().build::<i32, i32>().err(),
+			Some(CacheBuildError::MissingCapacity)
+		);
+	}
+
+	#[test]
+	fn pop_lru_with_frequency_returns_estimate() {
+		let mut cache = WTinyLfuCache::new(1000, 100);
+		for i in 0..5 {
+			cache.put(i, i);
+		}
+		for _ in 0..3 {
+			cache.observe(&0);
+		}
+		let frequency = cache.estimate_frequency(&0);
+		assert_eq!(frequency, 3);
+
+		assert_eq!(
+			cache.pop_lru_window_with_frequency(),
+			Some((0, 0, frequency))
+		);
+		assert_eq!(cache.pop_lru_window_with_frequency(), Some((1, 1, 0)));
+		assert_eq!(cache.pop_lru_main_with_frequency(), None);
+
+		cache.resize(200);
+		cache.get(&2);
+		assert_eq!(cache.pop_lru_main_with_frequency(), Some((2, 2, 1)));
+	}
+
+	#[test]
+	fn builder_splits_main_cache_by_protected_ratio() {
+		let cache: WTinyLfuCache<i32, i32> = WTinyLfuCacheBuilder::new()
+			.capacity(1000)
+			.protected_ratio(0.5)
+			.build()
+			.unwrap();
+		assert_eq!(cache.main_cap(), 990);
+		assert_eq!(cache.main_cache.segment_caps(), (495, 495));
+
+		let cache: WTinyLfuCache<i32, i32> = WTinyLfuCacheBuilder::new()
+			.capacity(3)
+			.protected_ratio(0.99)
+			.build()
+			.unwrap();
+		assert_eq!(cache.main_cache.segment_caps(), (1, 1));
+
+		for protected_ratio in [0.0, 1.0, 1.5, f64::NAN] {
+			let result = WTinyLfuCacheBuilder::new()
+				.capacity(1000)
+				.protected_ratio(protected_ratio)
+				.build::<i32, i32>();
+			assert_eq!(result.err(), Some(CacheBuildError::InvalidProtectedRatio));
+		}
+	}
+
+	#[test]
+	fn segment_caps_add_up_to_cap() {
+		for window_ratio in [0.01, 0.5, 0.95, 0.999] {
+			for protected_ratio in [0.8, 0.5, 1e-20] {
+				for cap in 1..200 {
+					let cache: WTinyLfuCache<i32, i32> = WTinyLfuCacheBuilder::new()
+						.capacity(cap)
+						.window_ratio(window_ratio)
+						.protected_ratio(protected_ratio)
+						.build()
+						.unwrap();
+					assert_eq!(cache.window_cap() + cache.main_cap(), cap.max(3));
+				}
+			}
+		}
+
+		// The window would get all but one slot, which leaves the protected segment without one.
+		let mut cache: WTinyLfuCache<i32, i32> = WTinyLfuCacheBuilder::new()
+			.capacity(10)
+			.window_ratio(0.95)
+			.build()
+			.unwrap();
+		assert_eq!(cache.window_cap(), 8);
+		assert_eq!(cache.main_cache.segment_caps(), (1, 1));
+
+		cache.resize(20);
+		assert_eq!(cache.window_cap(), 18);
+		assert_eq!(cache.main_cap(), 2);
+	}
+
+	#[test]
+	fn deterministic_hasher_reproduces_evictions() {
+		type Hasher = BuildHasherDefault<DefaultHasher>;
+
+		fn run() -> (Vec<(u64, u64, EvictReason)>, Vec<u64>) {
+			let mut cache = WTinyLfuCache::with_hasher(100, 1000, Hasher::default());
+			let evicted = Arc::new(Mutex::new(Vec::new()));
+			let on_evict = Arc::clone(&evicted);
+			cache.set_on_evict(move |k, v, reason| on_evict.lock().unwrap().push((k, v, reason)));
+
+			// A skewed, but fixed access pattern, so admission decisions depend on the sketch.
+			let mut k: u64 = 1;
+			for _ in 0..5000 {
+				k = k
+					.wrapping_mul(6364136223846793005)
+					.wrapping_add(1442695040888963407);
+				let key = (k >> 33) % 500 % ((k >> 20) % 500 + 1);
+				if cache.get(&key).is_none() {
+					cache.put(key, key);
+				}
+			}
+
+			let mut keys = cache.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+			keys.sort();
+			let evicted = evicted.lock().unwrap().clone();
+			(evicted, keys)
+		}
+
+		let (evicted, keys) = run();
+		assert!(evicted
+			.iter()
+			.any(|(_, _, reason)| *reason == EvictReason::Rejected));
+		assert_eq!(run(), (evicted, keys));
+	}
+
+	#[test]
+	fn record_miss_hot_crosses_threshold() {
+		let mut cache: WTinyLfuCache<i32, i32> = WTinyLfuCache::new(100, 1000);
+
+		assert_eq!(cache.get(&1), None);
+		assert!(!cache.record_miss_hot(&1, 3));
+		assert!(!cache.record_miss_hot(&1, 3));
+		assert!(cache.record_miss_hot(&1, 3));
+		assert_eq!(cache.estimate_frequency(&1), 3);
+		assert!(!cache.record_miss_hot(&2, 3));
+		assert_eq!(cache.stats().misses, 1);
+	}
+
+	#[test]
+	fn debug_prints_sizes() {
+		let mut cache = WTinyLfuCache::new(100, 10);
+		for i in 0..20 {
+			cache.put(i, i * 10);
+		}
+
+		let compact = format!("{:?}", cache);
+		assert!(compact.contains("cap: 100"));
+		assert!(compact.contains("len: 20"));
+		assert!(compact.contains("sample_size: 10"));
+		assert!(!compact.contains("entries"));
+
+		let pretty = format!("{:#?}", cache);
+		assert!(pretty.contains("entries"));
+		// Only the first 10 entries in `iter` order, i.e. 19 down to 10.
+		assert!(pretty.contains(" 19: 190,"));
+		assert!(pretty.contains(" 10: 100,"));
+		assert!(!pretty.contains(" 9: 90,"));
+	}
+
+	#[test]
+	fn len_matches_iter_after_random_operations() {
+		use rand::rngs::SmallRng;
+		use rand::{Rng, SeedableRng};
+
+		let mut rng = SmallRng::seed_from_u64(7);
+		let mut cache = WTinyLfuCache::new(200, 2000);
+		for _ in 0..20_000 {
+			let k = rng.gen_range(0..400);
+			match rng.gen_range(0..10) {
+				0..=3 => {
+					cache.put(k, k);
+				}
+				4..=7 => {
+					cache.get(&k);
+				}
+				8 => {
+					cache.pop(&k);
+				}
+				_ => {
+					cache.promote(&k);
+				}
+			}
+			if rng.gen_ratio(1, 500) {
+				cache.resize(rng.gen_range(50..400));
+			}
+		}
+
+		assert!(cache.verify_len());
+	}
+
+	#[test]
+	fn clone_is_independent_and_admits_identically() {
+		for policy in [WindowPolicy::Lru, WindowPolicy::Fifo, WindowPolicy::Sieve] {
+			let mut cache = WTinyLfuCache::with_window_policy(20, 20, policy);
+			for i in 0..40 {
+				cache.put(i, i * 10);
+				cache.get(&(i / 2));
+			}
+
+			let mut cloned = cache.clone();
+			assert_eq!(cloned.len(), cache.len());
+			assert!(cloned.iter().eq(cache.iter()));
+			for i in 0..40 {
+				assert_eq!(cloned.estimate_frequency(&i), cache.estimate_frequency(&i));
+			}
+
+			for i in 40..80 {
+				assert_eq!(cloned.put(i % 50, i), cache.put(i % 50, i));
+			}
+			assert!(cloned.iter().eq(cache.iter()));
+
+			cloned.clear();
+			assert!(!cache.is_empty());
+		}
+	}
+
+	#[test]
+	fn put_located_returns_segment_of_pair() {
+		let mut cache = WTinyLfuCache::new(100, 100);
+		assert_eq!(cache.put_located(1, 10), (None, Segment::Window));
+		assert_eq!(cache.put_located(1, 11), (Some(10), Segment::Window));
+
+		// Pushes the pair out of the window into the probationary segment.
+		cache.put(2, 20);
+		assert_eq!(cache.put_located(1, 12), (Some(11), Segment::Probationary));
+
+		cache.get(&1);
+		assert_eq!(cache.put_located(1, 13), (Some(12), Segment::Protected));
+		assert_eq!(cache.put_located(3, 30), (None, Segment::Window));
+	}
+
+	#[test]
+	fn keys_values_and_values_mut_match_iter() {
+		for policy in [WindowPolicy::Lru, WindowPolicy::Sieve] {
+			let mut cache = WTinyLfuCache::with_window_policy(10, 10, policy);
+			for i in 0..10 {
+				cache.put(i, i * 10);
+			}
+			cache.get(&3);
+			let frequencies: Vec<u16> = (0..10).map(|i| cache.estimate_frequency(&i)).collect();
+
+			assert!(cache.keys().eq(cache.iter().map(|(k, _)| k)));
+			assert!(cache.values().eq(cache.iter().map(|(_, v)| v)));
+
+			let order: Vec<i32> = cache.keys().copied().collect();
+			for v in cache.values_mut() {
+				*v += 1;
+			}
+			assert!(cache.iter().all(|(k, v)| *v == k * 10 + 1));
+			assert_eq!(cache.keys().copied().collect::<Vec<_>>(), order);
+			assert!((0..10)
+				.map(|i| cache.estimate_frequency(&i))
+				.eq(frequencies));
+		}
 	}
 
 	#[test]
-	fn clear_cache() {
+	fn estimate_all_matches_estimate_frequency() {
+		let mut cache = WTinyLfuCache::new(20, 20);
+		for i in 0..30 {
+			cache.put(i, i);
+			for _ in 0..i % 4 {
+				cache.get(&i);
+			}
+		}
+
+		let estimates = cache.estimate_all();
+		assert_eq!(estimates.len(), cache.len());
+		assert!(estimates.iter().map(|(k, _)| *k).eq(cache.keys()));
+		for (k, frequency) in estimates {
+			assert_eq!(frequency, cache.estimate_frequency(k));
+		}
+	}
+
+	#[test]
+	fn iter_mut_updates_values_without_accesses() {
+		for policy in [WindowPolicy::Fifo, WindowPolicy::Sieve] {
+			let mut cache = WTinyLfuCache::with_window_policy(10, 10, policy);
+			for i in 0..10 {
+				cache.put(i, i);
+			}
+			cache.get(&2);
+			let order: Vec<i32> = cache.keys().copied().collect();
+			let frequencies: Vec<u16> = (0..10).map(|i| cache.estimate_frequency(&i)).collect();
+
+			let mut visited = 0;
+			for (k, v) in cache.iter_mut() {
+				assert_eq!(k, v);
+				*v *= 2;
+				visited += 1;
+			}
+			assert_eq!(visited, cache.len());
+
+			assert!(order.iter().all(|i| cache.peek(i) == Some(&(i * 2))));
+			assert_eq!(cache.keys().copied().collect::<Vec<_>>(), order);
+			assert!((0..10)
+				.map(|i| cache.estimate_frequency(&i))
+				.eq(frequencies));
+		}
+	}
+
+	#[test]
+	fn protected_key_is_never_demoted() {
+		let mut cache = WTinyLfuCache::new(100, 100);
+		cache.put(0, 0);
+		assert!(!cache.protect_key(&0));
+		cache.put(1, 1);
+		assert!(cache.protect_key(&0));
+		assert_eq!(
+			cache.iter_by_segment().next(),
+			Some((Segment::Window, &1, &1))
+		);
+		assert!(cache
+			.iter_by_segment()
+			.any(|pair| pair == (Segment::Protected, &0, &0)));
+
+		// Every other key is promoted once it leaves the window, which demotes protected pairs as soon as the
+		// segment is full.
+		for i in 1..1000 {
+			cache.put(i, i);
+			cache.get(&i);
+			cache.get(&(i - 1));
+		}
+		assert!(cache
+			.iter_by_segment()
+			.any(|pair| pair == (Segment::Protected, &0, &0)));
+
+		// Once removed, the key is demoted like any other.
+		assert_eq!(cache.pop(&0), Some(0));
+		cache.observe_weighted(&0, 10);
+		cache.put(0, 0);
+		cache.put(1000, 1000);
+		cache.get(&0);
+		assert!(cache
+			.iter_by_segment()
+			.any(|pair| pair == (Segment::Protected, &0, &0)));
+		for i in 1001..2000 {
+			cache.put(i, i);
+			cache.get(&i);
+			cache.get(&(i - 1));
+		}
+		assert!(!cache
+			.iter_by_segment()
+			.any(|pair| pair == (Segment::Protected, &0, &0)));
+	}
+
+	#[test]
+	fn into_iter_yields_every_pair() {
 		let mut cache = WTinyLfuCache::new(10, 10);
-		cache.push(1, "one");
-		cache.push(2, "two");
-		assert_eq!(cache.get(&1), Some(&"one"));
-		assert_eq!(cache.get(&2), Some(&"two"));
+		for i in 0..10 {
+			cache.put(i, i * 10);
+			cache.get(&i);
+		}
+
+		let mut borrowed = Vec::new();
+		for (k, v) in &cache {
+			borrowed.push((*k, *v));
+		}
+		assert!(borrowed.iter().map(|(k, v)| (k, v)).eq(cache.iter()));
+
+		let into_iter = cache.into_iter();
+		assert_eq!(into_iter.len(), borrowed.len());
+		let mut owned: Vec<(i32, i32)> = into_iter.collect();
+		owned.sort();
+		borrowed.sort();
+		assert_eq!(owned, borrowed);
+	}
+
+	#[test]
+	fn drain_empties_cache_and_resets_frequency() {
+		let mut cache = WTinyLfuCache::new(10, 100);
+		for i in 0..10 {
+			cache.put(i, i);
+			cache.get(&i);
+			cache.get(&i);
+		}
+		let len = cache.len();
+
+		let mut drained: Vec<(i32, i32)> = cache.drain().collect();
+		assert_eq!(drained.len(), len);
+		drained.sort();
+		assert!(drained.iter().all(|(k, v)| k == v));
+		assert!(cache.is_empty());
+		assert_eq!(cache.frequency.sample_counter(), 0);
+		assert!((0..10).all(|i| cache.estimate_frequency(&i) == 0));
+
+		cache.put(1, 1);
+		cache.put(2, 2);
+		let mut drain = cache.drain();
+		assert_eq!(drain.next(), Some((2, 2)));
+		drop(drain);
+		assert!(cache.is_empty());
+	}
+
+	#[test]
+	fn compact_sketch_shrinks_oversized_sketch() {
+		let mut cache = WTinyLfuCache::new(1000, 100_000);
+		for i in 0..10 {
+			cache.put(i, i);
+		}
+		for _ in 0..20 {
+			cache.get(&1);
+		}
+		cache.get(&2);
+
+		let (width, depth) = cache.sketch_dimensions();
+		cache.compact_sketch();
+		assert_eq!(cache.frequency.sample_size(), 100);
+		assert!(cache.sketch_dimensions().0 < width);
+		assert_eq!(cache.sketch_dimensions().1, depth);
+		assert!(cache.estimate_frequency(&1) > cache.estimate_frequency(&2));
+		assert!(cache.estimate_frequency(&2) > cache.estimate_frequency(&3));
+
+		cache.compact_sketch();
+		assert_eq!(cache.frequency.sample_size(), 100);
+	}
+
+	#[test]
+	fn retain_keeps_matching_pairs_in_order() {
+		let mut cache = WTinyLfuCache::new(100, 100);
+		for i in 0..10 {
+			cache.put(i, i);
+		}
+		cache.get(&3);
+		cache.get(&4);
+		let frequencies: Vec<u16> = (0..10).map(|i| cache.estimate_frequency(&i)).collect();
+		let survivors: Vec<(Segment, i32)> = cache
+			.iter_by_segment()
+			.filter(|(_, k, _)| *k % 2 == 0)
+			.map(|(segment, k, _)| (segment, *k))
+			.collect();
+
+		cache.retain(|k, _| k % 2 == 0);
+		assert_eq!(
+			cache
+				.iter_by_segment()
+				.map(|(segment, k, _)| (segment, *k))
+				.collect::<Vec<_>>(),
+			survivors
+		);
+		let mut keys: Vec<i32> = cache.keys().copied().collect();
+		keys.sort();
+		assert_eq!(keys, [0, 2, 4, 6, 8]);
+		assert!((0..10)
+			.map(|i| cache.estimate_frequency(&i))
+			.eq(frequencies));
+	}
+
+	#[test]
+	fn get_disjoint_mut_handles_keys_in_same_segment() {
+		let mut cache = WTinyLfuCache::new(100, 100);
+		for i in 0..5 {
+			cache.put(i, i * 10);
+		}
+		cache.get(&1);
+		cache.get(&2);
+		assert!(cache.main_cache.is_protected(&1) && cache.main_cache.is_protected(&2));
+
+		let [one, two, missing] = cache.get_disjoint_mut([&1, &2, &100]);
+		let (one, two) = (one.unwrap(), two.unwrap());
+		mem::swap(one, two);
+		*one += 1;
+		assert_eq!(missing, None);
+
+		assert_eq!(cache.peek(&1), Some(&21));
+		assert_eq!(cache.peek(&2), Some(&10));
+		assert_eq!(cache.estimate_frequency(&1), 2);
+	}
+
+	#[test]
+	#[should_panic(expected = "must be distinct")]
+	fn get_disjoint_mut_panics_on_duplicate_keys() {
+		let mut cache = WTinyLfuCache::new(10, 10);
+		cache.put(1, 1);
+		cache.get_disjoint_mut([&1, &1]);
+	}
+
+	#[test]
+	fn collect_keeps_every_pair() {
+		for len in [0, 1, 2, 3, 10, 100, 1000] {
+			let cache: WTinyLfuCache<usize, usize> = (0..len).map(|i| (i, i * 2)).collect();
+			assert_eq!(cache.cap(), len.max(1));
+			assert_eq!(cache.len(), len);
+			assert!((0..len).all(|i| cache.peek(&i) == Some(&(i * 2))));
+		}
+
+		let cache: WTinyLfuCache<i32, i32> = [(1, 1), (2, 2), (1, 3)].into_iter().collect();
 		assert_eq!(cache.len(), 2);
-		assert_eq!(cache.cap(), 10);
-		assert_eq!(&iter_keys(&cache), &[1, 2]);
+		assert_eq!(cache.peek(&1), Some(&3));
+	}
 
-		cache.clear();
-		assert_eq!(cache.get(&1), None);
-		assert_eq!(cache.get(&2), None);
-		assert_eq!(cache.len(), 0);
-		assert_eq!(cache.cap(), 10);
-		assert_eq!(&iter_keys(&cache), &[]);
+	#[test]
+	fn extend_puts_pairs() {
+		let mut cache = WTinyLfuCache::new(100, 100);
+		cache.extend((0..10).map(|i| (i, i)));
+		cache.extend([(3, 30)]);
+		assert_eq!(cache.len(), 10);
+		assert_eq!(cache.peek(&3), Some(&30));
+	}
+
+	#[test]
+	fn to_lru_copies_stored_pairs_in_iter_order() {
+		let mut cache = WTinyLfuCache::new(100, 100);
+		for i in 0..10 {
+			cache.put(i, i * 10);
+		}
+		cache.get(&3);
+
+		let lru = cache.to_lru();
+		assert_eq!(lru.cap().get(), cache.len());
+		assert!(lru.iter().eq(cache.iter()));
+
+		let empty: WTinyLfuCache<i32, i32> = WTinyLfuCache::new(10, 10);
+		assert!(empty.to_lru().is_empty());
+	}
+
+	#[test]
+	fn get_or_insert_with_returns_stored_or_inserted_value() {
+		let evicted = Arc::new(Mutex::new(Vec::new()));
+		let mut cache = WTinyLfuCache::new(100, 100);
+		let sink = Arc::clone(&evicted);
+		cache.set_on_evict(move |k, v, _| sink.lock().unwrap().push((k, v)));
+
+		assert_eq!(*cache.get_or_insert_with(1, || 10), 10);
+		assert_eq!(*cache.get_or_insert_with(1, || unreachable!()), 10);
+		assert_eq!(cache.estimate_frequency(&1), 1);
+
+		*cache.get_or_insert_with_mut(2, || 20) += 1;
+		*cache.get_or_insert_with_mut(2, || unreachable!()) += 1;
+		assert_eq!(cache.peek(&2), Some(&22));
+		assert_eq!(cache.peek(&1), Some(&10));
+		assert_eq!(cache.stats().hits, 2);
+		assert_eq!(cache.stats().misses, 2);
+
+		for i in 3..200 {
+			assert_eq!(*cache.get_or_insert_with_mut(i, || i * 10), i * 10);
+		}
+		assert_eq!(evicted.lock().unwrap().len(), 200 - 1 - cache.len());
+	}
+
+	#[test]
+	fn get_or_try_insert_with_only_inserts_on_success() {
+		let mut cache = WTinyLfuCache::new(100, 100);
+		for i in 0..10 {
+			cache.put(i, i);
+		}
+
+		assert_eq!(cache.get_or_try_insert_with(1, || Err("unused")), Ok(&1));
+		assert_eq!(cache.estimate_frequency(&1), 1);
+
+		let order: Vec<(Segment, i32)> = cache
+			.iter_by_segment()
+			.map(|(segment, k, _)| (segment, *k))
+			.collect();
+		let stats = cache.stats();
+		let sample_counter = cache.frequency.sample_counter();
+		assert_eq!(
+			cache.get_or_try_insert_with(20, || Err("failed")),
+			Err("failed")
+		);
+		assert!(!cache.contains(&20));
+		assert!(cache
+			.iter_by_segment()
+			.map(|(segment, k, _)| (segment, *k))
+			.eq(order));
+		assert_eq!(cache.stats(), stats);
+		assert_eq!(cache.frequency.sample_counter(), sample_counter);
+		assert_eq!(cache.estimate_frequency(&20), 0);
+
+		assert_eq!(
+			cache.get_or_try_insert_with(20, || Ok::<_, &str>(200)),
+			Ok(&200)
+		);
+		assert_eq!(cache.peek(&20), Some(&200));
+		assert_eq!(cache.stats().misses, stats.misses + 1);
+	}
+
+	#[test]
+	fn get_counted_skips_doorkeeper() {
+		let mut cache = WTinyLfuCache::new(10, 100);
+		cache.put(1, 1);
+		cache.put(2, 2);
+
+		cache.get(&1);
+		cache.get_counted(&2);
+		assert_eq!(cache.estimate_frequency(&1), 1);
+		assert_eq!(cache.estimate_frequency(&2), 2);
+		assert_eq!(cache.frequency.sample_counter(), 1);
+
+		cache.get_counted(&2);
+		assert_eq!(cache.estimate_frequency(&2), 3);
+		assert_eq!(cache.get_counted(&3), None);
+		assert_eq!(cache.estimate_frequency(&3), 0);
+	}
+
+	#[test]
+	fn entry_inserts_or_modifies() {
+		let mut cache = WTinyLfuCache::new(100, 100);
+		assert_eq!(*cache.entry(1).or_insert(10), 10);
+		assert_eq!(*cache.entry(1).or_insert(20), 10);
+		assert_eq!(*cache.entry(2).or_insert_with(|| 20), 20);
+
+		cache.entry(1).and_modify(|v| *v += 1).or_insert(0);
+		cache.entry(3).and_modify(|v| *v += 1).or_insert(30);
+		assert_eq!(cache.peek(&1), Some(&11));
+		assert_eq!(cache.peek(&3), Some(&30));
+
+		for _ in 0..3 {
+			*cache.entry(4).or_insert(0) += 1;
+		}
+		assert_eq!(cache.peek(&4), Some(&3));
+		assert_eq!(cache.estimate_frequency(&4), 2);
+		assert_eq!(cache.stats().hits, 4);
+		assert_eq!(cache.stats().misses, 4);
+
+		match cache.entry(5) {
+			Entry::Vacant(entry) => assert_eq!(*entry.insert(50), 50),
+			Entry::Occupied(_) => unreachable!(),
+		}
+		match cache.entry(5) {
+			Entry::Occupied(mut entry) => {
+				assert_eq!(entry.key(), &5);
+				*entry.get_mut() += 1;
+				assert_eq!(entry.get(), &51);
+			}
+			Entry::Vacant(_) => unreachable!(),
+		}
+	}
+
+	#[test]
+	fn shrink_to_keeps_capacity() {
+		let mut cache: WTinyLfuCache<i32, i32> = (0..100).map(|i| (i, i)).collect();
+		assert_eq!(cache.len(), 100);
+
+		let probationary_lru = *cache.main_cache.peek_lru().unwrap().0;
+		let evicted = cache.shrink_to(60);
+		assert_eq!(evicted.len(), 40);
+		assert_eq!(evicted[0].0, probationary_lru);
+		assert_eq!(cache.len(), 60);
+		assert_eq!(cache.cap(), 100);
+		assert!(evicted.iter().all(|(k, _)| !cache.contains(k)));
+
+		assert!(cache.shrink_to(80).is_empty());
+		assert_eq!(cache.shrink_to(0).len(), 60);
+		assert!(cache.is_empty());
+	}
+
+	// Counts every key exactly, so the admission decisions of a cache using it are easy to predict.
+	struct ExactSketch<S> {
+		counts: HashMap<u64, u16>,
+		hash_builder: S,
+	}
+
+	impl<K: Hash, S: BuildHasher> FrequencySketch<K, S> for ExactSketch<S> {
+		fn with_capacity(_capacity: usize, hash_builder: S) -> Self {
+			Self {
+				counts: HashMap::new(),
+				hash_builder,
+			}
+		}
+
+		fn increment<Q>(&mut self, k: &Q) -> u16
+		where
+			K: Borrow<Q>,
+			Q: Hash + ?Sized,
+		{
+			let count = self
+				.counts
+				.entry(self.hash_builder.hash_one(k))
+				.or_insert(0);
+			*count += 1;
+			*count
+		}
+
+		fn estimate<Q>(&self, k: &Q) -> u16
+		where
+			K: Borrow<Q>,
+			Q: Hash + ?Sized,
+		{
+			self.counts
+				.get(&self.hash_builder.hash_one(k))
+				.copied()
+				.unwrap_or(0)
+		}
+
+		fn halve(&mut self) {
+			for count in self.counts.values_mut() {
+				*count /= 2;
+			}
+		}
+
+		fn decay(&mut self, factor: f64) {
+			for count in self.counts.values_mut() {
+				*count = (*count as f64 * factor) as u16;
+			}
+		}
+	}
+
+	#[test]
+	fn custom_sketch_drives_admission() {
+		let mut cache: WTinyLfuCache<u64, u64, RandomState, ExactSketch<RandomState>> =
+			WTinyLfuCacheBuilder::new()
+				.capacity(100)
+				.sample_size(1000)
+				.build_with_sketch(RandomState::new())
+				.unwrap();
+		for i in 0..100 {
+			cache.put(i, i);
+		}
+
+		// The first access only sets the doorkeeper, the sketch counts the other two exactly.
+		cache.observe_weighted(&1000, 3);
+		assert_eq!(cache.estimate_frequency(&1000), 3);
+		assert_eq!(cache.estimate_frequency(&2000), 0);
+
+		// The pair pushed out of the window ties with the probationary victim and is rejected, while the
+		// observed key wins against it.
+		let window_pair = cache.window_cache.iter().next().map(|(k, _)| *k).unwrap();
+		cache.put(1000, 1000);
+		assert!(!cache.contains(&window_pair));
+		cache.put(2000, 2000);
+		assert!(cache.contains(&1000));
+	}
+
+	#[test]
+	fn touch_records_access_of_stored_keys() {
+		let mut cache = WTinyLfuCache::new(100, 100);
+		cache.put(1, "one");
+
+		assert!(cache.touch(&1));
+		assert!(cache.touch(&1));
+		assert_eq!(cache.estimate_frequency(&1), 2);
+		assert_eq!(cache.stats().hits, 2);
+
+		assert!(!cache.touch(&2));
+		assert_eq!(cache.stats().misses, 0);
+		assert_eq!(cache.estimate_frequency(&2), 0);
+	}
+
+	#[test]
+	fn get_or_insert_with_limit_rejects_oversized_values() {
+		let mut cache = WTinyLfuCache::new(100, 100);
+		let err = cache.get_or_insert_with_limit(1, || vec![0u8; 10], 4, Vec::len);
+		assert_eq!(err, Err(vec![0u8; 10]));
+		assert!(!cache.contains(&1));
+		assert_eq!(cache.stats().misses, 0);
+
+		let v = cache.get_or_insert_with_limit(1, || vec![0u8; 4], 4, Vec::len);
+		v.unwrap().push(1);
+		assert_eq!(cache.stats().misses, 1);
+
+		// Hits aren't measured again.
+		let v = cache.get_or_insert_with_limit(1, Vec::new, 4, Vec::len);
+		assert_eq!(v.unwrap().len(), 5);
+		assert_eq!(cache.stats().hits, 1);
+	}
+
+	#[test]
+	fn segment_lengths_add_up() {
+		let mut cache: WTinyLfuCache<u64, u64> = (0..100).map(|i| (i, i)).collect();
+		for i in 0..50 {
+			cache.get(&i);
+			cache.get(&i);
+		}
+		for i in 100..120 {
+			cache.put(i, i);
+		}
+
+		assert_eq!(cache.window_len(), cache.window_cap());
+		assert_eq!(
+			cache.main_len(),
+			cache.probationary_len() + cache.protected_len()
+		);
+		assert_eq!(cache.window_len() + cache.main_len(), cache.len());
+		assert_eq!(cache.window_cap() + cache.main_cap(), cache.cap());
+		assert!(cache.protected_len() > 0);
+		assert!(cache.probationary_len() > 0);
+	}
+
+	#[test]
+	fn metadata_describes_stored_keys() {
+		let mut cache: WTinyLfuCache<u64, u64> = (0..100).map(|i| (i, i)).collect();
+		for _ in 0..3 {
+			cache.get(&5);
+		}
+		cache.get(&6);
+		cache.get(&6);
+
+		assert_eq!(
+			cache.metadata(&5),
+			Some(EntryMetadata {
+				frequency: 3,
+				segment: Segment::Protected,
+				lru_rank: 1,
+			})
+		);
+		assert_eq!(cache.metadata(&6).map(|m| m.lru_rank), Some(0));
+		let (rank, _) = cache.lru_rank(&99).unwrap();
+		assert_eq!(cache.metadata(&99).map(|m| m.lru_rank), Some(rank));
+		assert_eq!(
+			cache.metadata(&99).map(|m| m.segment),
+			Some(Segment::Window)
+		);
+		assert_eq!(cache.metadata(&100), None);
+	}
+
+	#[test]
+	fn eviction_listener_sees_dropped_pairs_once() {
+		let evicted = Arc::new(Mutex::new(Vec::new()));
+		let mut cache = {
+			let evicted = Arc::clone(&evicted);
+			WTinyLfuCache::new(100, 100)
+				.with_eviction_listener(move |k, _| evicted.lock().unwrap().push(k))
+		};
+		for i in 0..20 {
+			cache.put(i, i);
+		}
+
+		// Updates and promotions don't drop anything.
+		cache.put(0, 100);
+		cache.get(&0);
+		cache.get(&0);
+		assert!(evicted.lock().unwrap().is_empty());
+
+		// The promotion made room for 19, then the warm 20 pushes the cold probationary victim out.
+		cache.put(20, 20);
+		cache.get(&20);
+		cache.get(&20);
+		cache.put(21, 21);
+		assert_eq!(*evicted.lock().unwrap(), [1]);
+
+		let mut drain = cache.drain();
+		let first = drain.next().map(|(k, _)| k);
+		drop(drain);
+		let evicted = mem::take(&mut *evicted.lock().unwrap());
+		assert_eq!(evicted.len(), 1 + 20);
+		assert!(!evicted[1..].contains(&first.unwrap()));
+		assert!(cache.is_empty());
+	}
+
+	#[test]
+	fn retain_on_large_cache_keeps_order() {
+		for policy in [WindowPolicy::Lru, WindowPolicy::Sieve] {
+			let mut cache = WTinyLfuCache::with_window_policy(10_000, 100_000, policy);
+			cache.extend((0..10_000).map(|i| (i, i)));
+			for i in (0..10_000).step_by(7) {
+				cache.get(&i);
+			}
+			let survivors: Vec<(Segment, u64)> = cache
+				.iter_by_segment()
+				.filter(|(_, k, _)| *k % 3 != 0)
+				.map(|(segment, k, _)| (segment, *k))
+				.collect();
+			let len = cache.len();
+
+			cache.retain(|k, _| k % 3 != 0);
+			assert!(cache
+				.iter_by_segment()
+				.map(|(segment, k, _)| (segment, *k))
+				.eq(survivors.iter().copied()));
+			assert_eq!(cache.len(), survivors.len());
+			assert!(cache.len() < len);
+			assert!(cache.keys().all(|k| k % 3 != 0));
+		}
+	}
+
+	#[test]
+	fn stats_count_hits_and_admission_outcomes() {
+		let mut cache = WTinyLfuCache::new(100, 100);
+		for i in 0..20 {
+			cache.put(i, i);
+		}
+		let stats = cache.stats();
+		assert_eq!(
+			(stats.admissions, stats.rejections, stats.evictions),
+			(19, 0, 0)
+		);
+
+		// 19 loses against the main cache victim, then the warm 20 wins against it.
+		cache.put(20, 20);
+		cache.get(&20);
+		cache.get(&20);
+		cache.put(21, 21);
+		for i in 100..103 {
+			cache.get(&i);
+		}
+
+		let stats = cache.stats();
+		assert_eq!(
+			(stats.admissions, stats.rejections, stats.evictions),
+			(20, 1, 2)
+		);
+		assert_eq!((stats.hits, stats.misses), (2, 3));
+		assert_eq!(cache.hit_ratio(), 0.4);
+
+		cache.reset_stats();
+		assert_eq!(cache.stats(), Stats::default());
+		assert_eq!(cache.hit_ratio(), 0.0);
+	}
+
+	#[test]
+	fn merge_with_resolves_conflicts() {
+		let mut cache: WTinyLfuCache<u64, u64> = (0..50).map(|i| (i, i)).collect();
+		cache.get(&10);
+		cache.get(&10);
+		let other: WTinyLfuCache<u64, u64> = (40..60).map(|i| (i, 100 - i)).collect();
+
+		cache.merge_with(other, |_, existing, incoming| existing.max(incoming));
+		// 49 was in the window, so it was pushed out by the first new key before its turn came.
+		for i in 40..49 {
+			assert_eq!(cache.peek(&i), Some(&i.max(100 - i)));
+		}
+		assert!(!cache.contains(&49));
+		assert_eq!(cache.peek(&10), Some(&10));
+		assert!(cache.is_protected(&10));
+	}
+
+	#[test]
+	fn clear_if_clears_only_when_condition_holds() {
+		let mut cache = WTinyLfuCache::new(100, 100);
+		for i in 0..10 {
+			cache.put(i, i);
+			cache.get(&i);
+		}
+
+		assert!(!cache.clear_if(|cache| cache.len() > 50));
+		assert_eq!(cache.len(), 10);
+		assert_eq!(cache.estimate_frequency(&3), 1);
+
+		assert!(cache.clear_if(|cache| cache.contains(&3)));
+		assert!(cache.is_empty());
+		assert_eq!(cache.estimate_frequency(&3), 0);
+	}
+
+	#[test]
+	fn warm_start_frequencies_beat_fresh_pairs() {
+		let mut cache = WTinyLfuCache::warm_start(100, 1000, (0..100).map(|i| (i, i, 5)));
+		assert_eq!(cache.len(), 100);
+		assert_eq!(cache.estimate_frequency(&0), 5);
+
+		// The fresh pair can't displace the restored probationary victim once it leaves the window.
+		cache.push(100, 100);
+		assert_eq!(cache.push(101, 101), Some((100, 100)));
+		assert_eq!(cache.len(), 100);
+
+		let cache = WTinyLfuCache::warm_start(100, 100_000, [(1, 1, u16::MAX)]);
+		assert_eq!(cache.estimate_frequency(&1), u16::MAX / 2 - 1);
+	}
+
+	#[test]
+	fn into_iter_coldest_first_yields_ascending_frequency() {
+		let mut cache: WTinyLfuCache<u64, u64> = (0..50).map(|i| (i, i)).collect();
+		for i in 0..50 {
+			for _ in 0..(i * 7) % 5 {
+				cache.get(&i);
+			}
+		}
+		let frequencies: HashMap<u64, u16> =
+			(0..50).map(|i| (i, cache.estimate_frequency(&i))).collect();
+		let order: Vec<u64> = cache.clone().into_iter().map(|(k, _)| k).collect();
+
+		let coldest_first: Vec<u64> = cache.into_iter_coldest_first().map(|(k, _)| k).collect();
+		assert_eq!(coldest_first.len(), 50);
+		assert!(coldest_first
+			.windows(2)
+			.all(|pair| frequencies[&pair[0]] <= frequencies[&pair[1]]));
+
+		// Ties keep the `into_iter` order.
+		let mut expected = order;
+		expected.sort_by_key(|k| frequencies[k]);
+		assert_eq!(coldest_first, expected);
+	}
+
+	#[test]
+	fn halve_keeps_hot_keys_across_samples() {
+		let build = |decay_strategy| {
+			let mut cache: WTinyLfuCache<u64, u64> = WTinyLfuCacheBuilder::new()
+				.capacity(100)
+				.sample_size(50)
+				.decay_strategy(decay_strategy)
+				.build()
+				.unwrap();
+			cache.put(1, 1);
+			for _ in 0..20 {
+				cache.get(&1);
+			}
+			// Completes the sample with a key that isn't stored.
+			while cache.frequency.sample_counter() != 0 {
+				cache.observe(&1000);
+			}
+			cache
+		};
+
+		let halved = build(DecayStrategy::Halve);
+		assert_eq!(halved.frequency.decay_strategy(), DecayStrategy::Halve);
+		assert!(halved.frequency.estimate(&1) > 0);
+
+		let reset = build(DecayStrategy::Reset);
+		assert_eq!(reset.frequency.estimate(&1), 0);
+	}
+
+	#[test]
+	fn contents_similarity_is_jaccard_of_keys() {
+		let a: WTinyLfuCache<u64, u64> = (0..100).map(|i| (i, i)).collect();
+		let b: WTinyLfuCache<u64, u64> = (5..105).map(|i| (i, i * 2)).collect();
+		assert_eq!(a.len(), 100);
+		assert_eq!(b.len(), 100);
+
+		let similarity = a.contents_similarity(&b);
+		assert_eq!(similarity, 95.0 / 105.0);
+		assert!(similarity > 0.9 && similarity < 1.0);
+		assert_eq!(b.contents_similarity(&a), similarity);
+		assert_eq!(a.contents_similarity(&a), 1.0);
+
+		let empty: WTinyLfuCache<u64, u64> = WTinyLfuCache::new(10, 10);
+		assert_eq!(a.contents_similarity(&empty), 0.0);
+		assert_eq!(empty.contents_similarity(&empty.clone()), 1.0);
+	}
+
+	#[test]
+	fn repeated_puts_count_as_accesses() {
+		let mut cache = WTinyLfuCache::new(100, 10);
+		cache.put(1, 0);
+		assert_eq!(cache.estimate_frequency(&1), 0);
+
+		let mut estimates = Vec::new();
+		for i in 1..5 {
+			cache.put(1, i);
+			estimates.push(cache.estimate_frequency(&1));
+		}
+		assert_eq!(estimates, [1, 2, 3, 4]);
+		assert_eq!(cache.push(1, 5), Some((1, 4)));
+		assert_eq!(cache.estimate_frequency(&1), 5);
+
+		// The updates advance the sample like reads do, so the sketch is still halved when it's complete.
+		for i in 0..6 {
+			cache.put(1, i);
+		}
+		assert_eq!(cache.frequency.sample_counter(), 0);
+		assert!(cache.estimate_frequency(&1) < 10);
+		assert_eq!(cache.stats().hits, 0);
+	}
+
+	#[test]
+	fn get_mut_dirty_marks_pairs_until_taken() {
+		let mut cache: WTinyLfuCache<u64, u64> = (0..10).map(|i| (i, i)).collect();
+		assert!(cache.take_dirty().is_empty());
+
+		*cache.get_mut_dirty(&3).unwrap() += 10;
+		cache.get_mut_dirty(&7);
+		assert_eq!(cache.get_mut_dirty(&42), None);
+		assert_eq!(cache.estimate_frequency(&3), 1);
+		assert_eq!(cache.stats().hits, 2);
+		assert_eq!(cache.stats().misses, 1);
+
+		// Updates keep the mark, plain accesses don't set it.
+		cache.put(7, 70);
+		cache.get_mut(&5);
+		let mut dirty = cache.take_dirty();
+		dirty.sort_unstable();
+		assert_eq!(dirty, [3, 7]);
+		assert_eq!(cache.peek(&3), Some(&13));
+		assert!(cache.take_dirty().is_empty());
+
+		cache.get_mut_dirty(&1);
+		cache.pop(&1);
+		cache.put(1, 1);
+		assert!(cache.take_dirty().is_empty());
+	}
+
+	#[test]
+	fn resize_evicting_returns_discarded_pairs() {
+		let mut cache: WTinyLfuCache<u64, u64> = WTinyLfuCacheBuilder::new()
+			.capacity(100)
+			.window_ratio(0.5)
+			.build()
+			.unwrap();
+		for i in 0..100 {
+			cache.put(i, i);
+		}
+		for i in 0..100 {
+			cache.get(&i);
+		}
+		for i in 100..110 {
+			cache.put(i, i);
+		}
+		let len = cache.len();
+		assert!(
+			cache.window_len() > 0 && cache.probationary_len() > 0 && cache.protected_len() > 0
+		);
+
+		let evicted = Arc::new(Mutex::new(0));
+		let counter = Arc::clone(&evicted);
+		cache.set_on_evict(move |_, _, _| *counter.lock().unwrap() += 1);
+
+		let mut discarded = cache.resize_evicting(10);
+		assert_eq!(discarded.len(), len - cache.len());
+		assert!(cache.window_len() <= cache.window_cap());
+		assert_eq!(*evicted.lock().unwrap(), 0);
+
+		discarded.extend(cache.iter().map(|(k, v)| (*k, *v)));
+		discarded.sort_unstable();
+		discarded.dedup();
+		assert_eq!(discarded.len(), len);
+
+		assert!(cache.resize_evicting(20).is_empty());
+	}
+
+	#[test]
+	fn resize_checked_reports_discarded_pairs() {
+		let mut cache: WTinyLfuCache<u64, u64> = (0..50).map(|i| (i, i)).collect();
+		assert!(!cache.resize_checked(50));
+		assert!(!cache.resize_checked(200));
+		assert_eq!(cache.len(), 50);
+
+		assert!(cache.resize_checked(20));
+		assert!(cache.len() <= 20);
+		assert!(!cache.resize_checked(20));
+	}
+
+	#[test]
+	fn cap_returns_requested_capacity() {
+		for cap in 1..=300 {
+			let mut cache: WTinyLfuCache<i32, i32> = WTinyLfuCache::new(cap, 10);
+			assert_eq!(cache.cap(), cap);
+			assert!(cache.window_cache.cap().get() + cache.main_cache.cap() >= cap);
+
+			cache.resize(cap * 3);
+			assert_eq!(cache.cap(), cap * 3);
+			assert!(cache.window_cache.cap().get() + cache.main_cache.cap() >= cap * 3);
+		}
 	}
 }
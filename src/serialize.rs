@@ -0,0 +1,219 @@
+use crate::slot::Slot;
+use crate::{DecayStrategy, FrequencySketch, Segment, WTinyLfuCache, WindowPolicy};
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use serde::{Deserialize as DeriveDeserialize, Serialize as DeriveSerialize};
+use std::hash::{BuildHasher, Hash};
+
+// The serialized form of a cache. The entries are listed like `iter_by_segment` yields them, each with its
+// estimated access frequency, so the sketch can be seeded again with a hasher seeded differently.
+#[derive(DeriveSerialize)]
+struct CacheRef<'a, K, V> {
+	cap: usize,
+	window_cap: usize,
+	window_ratio: f64,
+	probationary_ratio: f64,
+	window_policy: WindowPolicy,
+	decay_strategy: DecayStrategy,
+	sample_size: usize,
+	sample_counter: usize,
+	entries: Vec<(Segment, &'a K, &'a V, u16)>,
+}
+
+#[derive(DeriveDeserialize)]
+struct CacheRepr<K, V> {
+	cap: usize,
+	window_cap: usize,
+	window_ratio: f64,
+	probationary_ratio: f64,
+	window_policy: WindowPolicy,
+	decay_strategy: DecayStrategy,
+	sample_size: usize,
+	sample_counter: usize,
+	entries: Vec<(Segment, K, V, u16)>,
+}
+
+/// Serializes the configuration, the progress of the current sample and every entry along with its segment
+/// and its estimated access frequency. The sketch and the doorkeeper themselves aren't serialized, since their
+/// contents depend on the seed of the hasher. Statistics and the eviction callback aren't serialized either.
+impl<K, V, S, C> Serialize for WTinyLfuCache<K, V, S, C>
+where
+	K: Hash + Eq + Serialize,
+	V: Serialize,
+	S: BuildHasher + Clone,
+	C: FrequencySketch<K, S>,
+{
+	fn serialize<T: Serializer>(&self, serializer: T) -> Result<T::Ok, T::Error> {
+		CacheRef {
+			cap: self.cap,
+			window_cap: self.window_cap(),
+			window_ratio: self.window_ratio,
+			probationary_ratio: self.main_cache.probationary_ratio(),
+			window_policy: self.window_cache.policy(),
+			decay_strategy: self.frequency.decay_strategy(),
+			sample_size: self.frequency.sample_size(),
+			sample_counter: self.frequency.sample_counter(),
+			entries: self
+				.iter_by_segment()
+				.map(|(segment, k, v)| (segment, k, v, self.frequency.estimate(k)))
+				.collect(),
+		}
+		.serialize(serializer)
+	}
+}
+
+/// Rebuilds the segments with the serialized entries in their previous order and seeds a new sketch and
+/// doorkeeper with the serialized estimates, hashing with `S::default()`.
+///
+/// The restored estimates are approximate: seeding a sketch adds collisions of its own, and the estimates of
+/// keys that weren't stored are lost. Pairs kept from demotion with `protect_key` are restored unmarked, and a
+/// SIEVE window forgets which pairs were visited.
+impl<'de, K, V, S> Deserialize<'de> for WTinyLfuCache<K, V, S>
+where
+	K: Hash + Eq + Deserialize<'de>,
+	V: Deserialize<'de>,
+	S: BuildHasher + Clone + Default,
+{
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let repr = CacheRepr::<K, V>::deserialize(deserializer)?;
+		if !(repr.window_ratio > 0.0 && repr.window_ratio < 1.0) {
+			return Err(de::Error::custom(
+				"window ratio must be between 0.0 and 1.0",
+			));
+		}
+		if !(repr.probationary_ratio > 0.0 && repr.probationary_ratio < 1.0) {
+			return Err(de::Error::custom(
+				"probationary ratio must be between 0.0 and 1.0",
+			));
+		}
+		if repr.sample_counter >= repr.sample_size {
+			return Err(de::Error::custom(
+				"sample counter must be below the sample size",
+			));
+		}
+
+		let mut cache = Self::from_builder(
+			repr.cap,
+			repr.sample_size,
+			repr.window_policy,
+			repr.window_ratio,
+			repr.probationary_ratio,
+			repr.decay_strategy,
+			S::default(),
+		);
+		if repr.window_cap != cache.window_cap() {
+			if repr.window_cap == 0 || repr.window_cap > repr.cap.saturating_sub(2) {
+				return Err(de::Error::custom("invalid window capacity"));
+			}
+			cache.set_window_capacity(repr.window_cap);
+		}
+
+		// Every segment is listed from its most recently used pair on, so it's rebuilt from the back.
+		for (segment, k, v, frequency) in repr.entries.into_iter().rev() {
+			if cache.contains(&k) {
+				return Err(de::Error::custom("duplicate key"));
+			}
+
+			cache.frequency.increment_by(&k, frequency as usize);
+			let entry = Slot::new(v, &mut cache.sequence);
+			let evicted = match segment {
+				Segment::Window => cache.window_cache.push_new(k, entry),
+				Segment::Probationary => cache.main_cache.push_new(k, entry),
+				Segment::Protected => cache.main_cache.push_new_protected(k, entry),
+			};
+			if evicted.is_some() {
+				return Err(de::Error::custom(
+					"segment holds more pairs than its capacity",
+				));
+			}
+		}
+		cache.frequency.set_sample_counter(repr.sample_counter);
+
+		Ok(cache)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::CacheRef;
+	use crate::{DecayStrategy, Segment, WTinyLfuCache, WindowPolicy};
+
+	// Serializes a cache of ten pairs with its window capacity and entries replaced.
+	fn corrupted(window_cap: usize, entries: Vec<(Segment, &u64, &u64, u16)>) -> Vec<u8> {
+		let cache: WTinyLfuCache<u64, u64> = (0..10).map(|i| (i, i)).collect();
+		bincode::serialize(&CacheRef {
+			cap: cache.cap,
+			window_cap,
+			window_ratio: cache.window_ratio,
+			probationary_ratio: cache.main_cache.probationary_ratio(),
+			window_policy: WindowPolicy::Lru,
+			decay_strategy: DecayStrategy::Halve,
+			sample_size: cache.frequency.sample_size(),
+			sample_counter: 0,
+			entries,
+		})
+		.unwrap()
+	}
+
+	#[test]
+	fn round_trip_keeps_entries_and_estimates() {
+		for policy in [WindowPolicy::Lru, WindowPolicy::Sieve] {
+			let mut cache = WTinyLfuCache::with_window_policy(200, 1000, policy);
+			for i in 0..150u64 {
+				cache.put(i, i.to_string());
+			}
+			for i in 0..150 {
+				for _ in 0..i % 4 {
+					cache.get(&i);
+				}
+			}
+
+			let bytes = bincode::serialize(&cache).unwrap();
+			let restored: WTinyLfuCache<u64, String> = bincode::deserialize(&bytes).unwrap();
+			let entries = |cache: &WTinyLfuCache<u64, String>| {
+				cache
+					.iter_by_segment()
+					.map(|(segment, k, v)| (segment, *k, v.clone()))
+					.collect::<Vec<_>>()
+			};
+			assert_eq!(entries(&restored), entries(&cache));
+			assert_eq!(restored.len(), cache.len());
+			assert_eq!(restored.window_cap(), cache.window_cap());
+			assert_eq!(restored.main_cap(), cache.main_cap());
+			assert!(restored.protected_len() > 0);
+			for (k, _) in cache.iter() {
+				assert!(restored.estimate_frequency(k) >= cache.estimate_frequency(k));
+			}
+			assert_eq!(
+				restored
+					.iter_by_segment()
+					.next()
+					.map(|(segment, _, _)| segment),
+				Some(Segment::Window)
+			);
+		}
+	}
+
+	#[test]
+	fn rejects_overfull_segments() {
+		// The window of a cache with room for ten pairs holds one.
+		let entries = vec![(Segment::Window, &1, &1, 0), (Segment::Window, &2, &2, 0)];
+		let bytes = corrupted(1, entries);
+		assert!(bincode::deserialize::<WTinyLfuCache<u64, u64>>(&bytes).is_err());
+
+		let bytes = corrupted(1, vec![(Segment::Window, &1, &1, 0)]);
+		assert!(bincode::deserialize::<WTinyLfuCache<u64, u64>>(&bytes).is_ok());
+	}
+
+	#[test]
+	fn rejects_invalid_window_capacities() {
+		for window_cap in [0, 9, usize::MAX - 1, usize::MAX] {
+			let bytes = corrupted(window_cap, Vec::new());
+			assert!(bincode::deserialize::<WTinyLfuCache<u64, u64>>(&bytes).is_err());
+		}
+
+		let bytes = corrupted(8, Vec::new());
+		let cache: WTinyLfuCache<u64, u64> = bincode::deserialize(&bytes).unwrap();
+		assert_eq!(cache.window_cap(), 8);
+	}
+}
@@ -0,0 +1,308 @@
+use std::borrow::Borrow;
+use std::cmp;
+use std::collections::hash_map::RandomState;
+use std::f64::consts::LN_2;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::marker::PhantomData;
+
+pub(crate) const SKETCH_PROBABILITY: f64 = 0.97;
+pub(crate) const SKETCH_TOLERANCE: f64 = 4.0;
+
+// Seeds that derive independent hash functions from the hasher of the cache, which may be deterministic.
+const SKETCH_SEEDS: [u64; 2] = [0x243f_6a88_85a3_08d3, 0x1319_8a2e_0370_7344];
+const DOORKEEPER_SEEDS: [u64; 2] = [0xa409_3822_299f_31d0, 0x082e_fa98_ec4e_6c89];
+
+// Hashes the key once per seed. Further hash functions are derived from the pair by double hashing.
+#[inline]
+fn hash_pair<S: BuildHasher, K: Hash + ?Sized>(
+	hash_builder: &S,
+	seeds: [u64; 2],
+	k: &K,
+) -> [u64; 2] {
+	seeds.map(|seed| {
+		let mut hasher = hash_builder.build_hasher();
+		hasher.write_u64(seed);
+		k.hash(&mut hasher);
+		hasher.finish()
+	})
+}
+
+#[inline]
+fn nth_hash(hashes: [u64; 2], n: usize) -> u64 {
+	hashes[0].wrapping_add((n as u64).wrapping_mul(hashes[1]))
+}
+
+/// Estimates how often keys were accessed for the admission policy of a [`WTinyLfuCache`].
+///
+/// The cache pairs the sketch with a doorkeeper, so a key's first access within a sample never reaches the
+/// sketch. At the end of every sample the sketch is aged with `halve`, or with `decay(0.0)` under
+/// [`DecayStrategy::Reset`]. Implementations only have to keep the relative order of the estimates roughly
+/// intact, the cache never relies on exact counts.
+///
+/// [`DecayStrategy::Reset`]: crate::DecayStrategy::Reset
+/// [`WTinyLfuCache`]: crate::WTinyLfuCache
+pub trait FrequencySketch<K, S> {
+	/// Creates a sketch for roughly `capacity` distinct keys, hashing them with `hash_builder`.
+	fn with_capacity(capacity: usize, hash_builder: S) -> Self;
+
+	/// Records an access to the key and returns its new estimate.
+	fn increment<Q>(&mut self, k: &Q) -> u16
+	where
+		K: Borrow<Q>,
+		Q: Hash + ?Sized;
+
+	/// Returns the estimated number of accesses to the key.
+	fn estimate<Q>(&self, k: &Q) -> u16
+	where
+		K: Borrow<Q>,
+		Q: Hash + ?Sized;
+
+	/// Halves every estimate at the end of a sample, rounding down, so older accesses weigh less than recent
+	/// ones.
+	fn halve(&mut self);
+
+	/// Multiplies every estimate by `factor`, which is between `0.0` and `1.0`, rounding down.
+	fn decay(&mut self, factor: f64);
+}
+
+/// The default [`FrequencySketch`]: a count-min sketch with 16-bit counters and conservative updates.
+pub struct CountMinSketch<K, S = RandomState> {
+	counters: Vec<u16>,
+	width: usize,
+	depth: usize,
+	hash_builder: S,
+	phantom_k: PhantomData<fn(&K)>,
+}
+
+impl<K: Hash, S: BuildHasher> FrequencySketch<K, S> for CountMinSketch<K, S> {
+	#[inline]
+	fn with_capacity(capacity: usize, hash_builder: S) -> Self {
+		Self::new(capacity, SKETCH_PROBABILITY, SKETCH_TOLERANCE, hash_builder)
+	}
+
+	// Only increments the counters that hold the current minimum, which keeps overestimation lower. Returns the
+	// new estimate of the key, which is also the highest counter this call changed.
+	fn increment<Q>(&mut self, k: &Q) -> u16
+	where
+		K: Borrow<Q>,
+		Q: Hash + ?Sized,
+	{
+		let hashes = hash_pair(&self.hash_builder, SKETCH_SEEDS, k);
+		let estimate = self.estimate_hashed(hashes);
+		let incremented = estimate.saturating_add(1);
+
+		for row in 0..self.depth {
+			let idx = self.index(hashes, row);
+			if self.counters[idx] == estimate {
+				self.counters[idx] = incremented;
+			}
+		}
+
+		incremented
+	}
+
+	#[inline]
+	fn estimate<Q>(&self, k: &Q) -> u16
+	where
+		K: Borrow<Q>,
+		Q: Hash + ?Sized,
+	{
+		self.estimate_hashed(hash_pair(&self.hash_builder, SKETCH_SEEDS, k))
+	}
+
+	fn halve(&mut self) {
+		for counter in &mut self.counters {
+			*counter /= 2;
+		}
+	}
+
+	fn decay(&mut self, factor: f64) {
+		for counter in &mut self.counters {
+			*counter = (*counter as f64 * factor) as u16;
+		}
+	}
+}
+
+impl<K: Hash, S: BuildHasher> CountMinSketch<K, S> {
+	pub(crate) fn new(capacity: usize, probability: f64, tolerance: f64, hash_builder: S) -> Self {
+		let (width, depth) = Self::dimensions_for(capacity, probability, tolerance);
+
+		Self {
+			counters: vec![0; width * depth],
+			width,
+			depth,
+			hash_builder,
+			phantom_k: PhantomData,
+		}
+	}
+
+	// Returns the `(width, depth)` a sketch created with these parameters allocates. The width is rounded up
+	// to a power of two.
+	pub(crate) fn dimensions_for(
+		capacity: usize,
+		probability: f64,
+		tolerance: f64,
+	) -> (usize, usize) {
+		let width = (2.0 / (tolerance / capacity as f64)).round() as usize;
+		let width = cmp::max(2, width)
+			.checked_next_power_of_two()
+			.expect("sketch width overflow");
+		let depth = cmp::max(1, ((1.0 - probability).ln() / 0.5f64.ln()) as usize);
+
+		(width, depth)
+	}
+
+	#[inline]
+	pub(crate) fn dimensions(&self) -> (usize, usize) {
+		(self.width, self.depth)
+	}
+
+	fn estimate_hashed(&self, hashes: [u64; 2]) -> u16 {
+		(0..self.depth)
+			.map(|row| self.counters[self.index(hashes, row)])
+			.min()
+			.expect("depth is at least 1")
+	}
+
+	#[inline]
+	fn index(&self, hashes: [u64; 2], row: usize) -> usize {
+		row * self.width + (nth_hash(hashes, row) as usize & (self.width - 1))
+	}
+}
+
+impl<K, S: Clone> Clone for CountMinSketch<K, S> {
+	fn clone(&self) -> Self {
+		Self {
+			counters: self.counters.clone(),
+			width: self.width,
+			depth: self.depth,
+			hash_builder: self.hash_builder.clone(),
+			phantom_k: PhantomData,
+		}
+	}
+}
+
+// Bloom filter used as the doorkeeper, which only remembers whether a key was seen.
+pub(crate) struct Doorkeeper<K, S> {
+	bits: Vec<u64>,
+	bit_count: usize,
+	hash_count: usize,
+	hash_builder: S,
+	phantom_k: PhantomData<fn(&K)>,
+}
+
+impl<K: Hash, S: BuildHasher> Doorkeeper<K, S> {
+	pub(crate) fn new(items_count: usize, fp_rate: f64, hash_builder: S) -> Self {
+		let bit_count = Self::bitmap_size(items_count, fp_rate) * 8;
+		let hash_count = cmp::max(
+			1,
+			(bit_count as f64 / items_count as f64 * LN_2).ceil() as usize,
+		);
+
+		Self {
+			bits: vec![0; bit_count.div_ceil(64)],
+			bit_count,
+			hash_count,
+			hash_builder,
+			phantom_k: PhantomData,
+		}
+	}
+
+	// Returns the number of bytes needed to keep the false positive rate at `fp_rate` with `items_count` keys.
+	pub(crate) fn bitmap_size(items_count: usize, fp_rate: f64) -> usize {
+		assert!(
+			items_count > 0,
+			"doorkeeper needs room for at least one key"
+		);
+		((items_count as f64) * fp_rate.ln() / (-8.0 * LN_2 * LN_2)).ceil() as usize
+	}
+
+	pub(crate) fn set<Q>(&mut self, k: &Q)
+	where
+		K: Borrow<Q>,
+		Q: Hash + ?Sized,
+	{
+		let hashes = hash_pair(&self.hash_builder, DOORKEEPER_SEEDS, k);
+		for n in 0..self.hash_count {
+			let bit = self.bit(hashes, n);
+			self.bits[bit / 64] |= 1 << (bit % 64);
+		}
+	}
+
+	// Can return false positives, but never false negatives.
+	pub(crate) fn check<Q>(&self, k: &Q) -> bool
+	where
+		K: Borrow<Q>,
+		Q: Hash + ?Sized,
+	{
+		let hashes = hash_pair(&self.hash_builder, DOORKEEPER_SEEDS, k);
+		(0..self.hash_count).all(|n| {
+			let bit = self.bit(hashes, n);
+			self.bits[bit / 64] & (1 << (bit % 64)) != 0
+		})
+	}
+
+	pub(crate) fn clear(&mut self) {
+		self.bits.fill(0);
+	}
+
+	#[inline]
+	fn bit(&self, hashes: [u64; 2], n: usize) -> usize {
+		(nth_hash(hashes, n) % self.bit_count as u64) as usize
+	}
+}
+
+impl<K, S: Clone> Clone for Doorkeeper<K, S> {
+	fn clone(&self) -> Self {
+		Self {
+			bits: self.bits.clone(),
+			bit_count: self.bit_count,
+			hash_count: self.hash_count,
+			hash_builder: self.hash_builder.clone(),
+			phantom_k: PhantomData,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{CountMinSketch, Doorkeeper, FrequencySketch};
+	use std::collections::hash_map::RandomState;
+
+	#[test]
+	fn sketch_counts_and_halves() {
+		let mut sketch: CountMinSketch<u64, _> =
+			CountMinSketch::new(100, 0.97, 4.0, RandomState::new());
+		assert_eq!(sketch.dimensions(), (64, 5));
+
+		for _ in 0..300 {
+			sketch.increment(&1);
+		}
+		assert_eq!(sketch.estimate(&1), 300);
+
+		sketch.halve();
+		assert_eq!(sketch.estimate(&1), 150);
+
+		sketch.decay(0.1);
+		assert_eq!(sketch.estimate(&1), 15);
+
+		let cloned = sketch.clone();
+		sketch.halve();
+		assert_eq!(sketch.estimate(&1), 7);
+		assert_eq!(cloned.estimate(&1), 15);
+	}
+
+	#[test]
+	fn doorkeeper_remembers_set_keys() {
+		let mut doorkeeper: Doorkeeper<u64, _> = Doorkeeper::new(100, 0.01, RandomState::new());
+		assert_eq!(Doorkeeper::<u64, RandomState>::bitmap_size(100, 0.01), 120);
+
+		for i in 0..100 {
+			doorkeeper.set(&i);
+		}
+		assert!((0..100).all(|i| doorkeeper.check(&i)));
+
+		doorkeeper.clear();
+		assert!((0..100).all(|i| !doorkeeper.check(&i)));
+	}
+}
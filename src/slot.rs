@@ -0,0 +1,38 @@
+/// A value stored in the cache along with the metadata the cache keeps for it. Slots are moved between
+/// segments as a whole, so the metadata follows the pair.
+#[derive(Clone)]
+pub(crate) struct Slot<V> {
+	pub(crate) v: V,
+	/// Whether the pair was accessed with `get_mut_dirty` since the last `take_dirty`.
+	pub(crate) dirty: bool,
+	/// Sequence number of the last `get` or `put` of the pair.
+	#[cfg(feature = "global-lru")]
+	pub(crate) seq: u64,
+}
+
+impl<V> Slot<V> {
+	#[cfg_attr(not(feature = "global-lru"), allow(unused_variables))]
+	#[inline]
+	pub(crate) fn new(v: V, sequence: &mut u64) -> Self {
+		let mut entry = Self {
+			v,
+			dirty: false,
+			#[cfg(feature = "global-lru")]
+			seq: 0,
+		};
+		entry.touch(sequence);
+
+		entry
+	}
+
+	// Stamps the entry with the next sequence number.
+	#[cfg_attr(not(feature = "global-lru"), allow(unused_variables))]
+	#[inline]
+	pub(crate) fn touch(&mut self, sequence: &mut u64) {
+		#[cfg(feature = "global-lru")]
+		{
+			*sequence += 1;
+			self.seq = *sequence;
+		}
+	}
+}
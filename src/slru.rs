@@ -1,78 +1,207 @@
+use crate::Segment;
 use lru::LruCache;
 use std::borrow::Borrow;
 use std::cmp;
-use std::hash::Hash;
+use std::collections::HashSet;
+use std::hash::{BuildHasher, Hash};
+use std::iter;
 use std::num::NonZeroUsize;
 
-pub(crate) struct SlruCache<K: Hash + Eq, V> {
-	probationary_segment: LruCache<K, V>,
-	protected_segment: LruCache<K, V>,
+pub(crate) const DEFAULT_PROBATIONARY_RATIO: f64 = 0.2;
+
+pub(crate) type Iter<'a, K, V> = iter::Chain<lru::Iter<'a, K, V>, lru::Iter<'a, K, V>>;
+
+pub(crate) struct SlruCache<K: Hash + Eq, V, S> {
+	probationary_segment: LruCache<K, V, S>,
+	protected_segment: LruCache<K, V, S>,
+	// Share of the capacity that goes to the probationary segment. Stored instead of the protected share so
+	// the default split rounds exactly like `cap * 0.2`.
+	probationary_ratio: f64,
+	// Protected keys that are never chosen for demotion, see `protect`. Every marked key is stored in the
+	// protected segment, and they always leave room for at least one pair that can be demoted.
+	marked_keys: HashSet<K, S>,
 }
 
-impl<K: Hash + Eq, V> SlruCache<K, V> {
-	pub(crate) fn new(cap: usize) -> Self {
-		let f64_cap = cap as f64;
-		let probationary_cap =
-			NonZeroUsize::new(cmp::max(1, (f64_cap * 0.2) as usize)).expect("non zero size");
-		let protected_cap =
-			NonZeroUsize::new(cmp::max(1, cap - probationary_cap.get())).expect("non zero size");
+impl<K: Hash + Eq, V, S: BuildHasher + Clone> SlruCache<K, V, S> {
+	pub(crate) fn new(cap: usize, probationary_ratio: f64, hash_builder: S) -> Self {
+		let (probationary_cap, protected_cap) = segment_caps(cap, probationary_ratio);
 
 		Self {
-			probationary_segment: LruCache::new(probationary_cap),
-			protected_segment: LruCache::new(protected_cap),
+			probationary_segment: LruCache::with_hasher(probationary_cap, hash_builder.clone()),
+			protected_segment: LruCache::with_hasher(protected_cap, hash_builder.clone()),
+			probationary_ratio,
+			marked_keys: HashSet::with_hasher(hash_builder),
 		}
 	}
 
-	pub(crate) fn put(&mut self, k: K, v: V) -> Option<V> {
+	#[cfg(test)]
+	pub(crate) fn push(&mut self, k: K, v: V) -> Option<(K, V)> {
 		if self.probationary_segment.contains(&k) {
-			return self.probationary_segment.put(k, v);
+			return self.probationary_segment.push(k, v);
 		}
 
 		if self.protected_segment.contains(&k) {
-			return self.protected_segment.put(k, v);
+			return self.protected_segment.push(k, v);
 		}
 
-		self.probationary_segment.put(k, v)
+		self.probationary_segment.push(k, v)
 	}
 
-	pub(crate) fn push(&mut self, k: K, v: V) -> Option<(K, V)> {
-		if self.probationary_segment.contains(&k) {
-			return self.probationary_segment.push(k, v);
-		}
+	// Inserts a key that isn't stored in either segment into the probationary segment, without looking it up
+	// first.
+	#[inline]
+	pub(crate) fn push_new(&mut self, k: K, v: V) -> Option<(K, V)> {
+		self.probationary_segment.push(k, v)
+	}
 
-		if self.protected_segment.contains(&k) {
-			return self.protected_segment.push(k, v);
+	// Same as `push_new`, but inserts into the protected segment.
+	#[cfg(feature = "serde")]
+	pub(crate) fn push_new_protected(&mut self, k: K, v: V) -> Option<(K, V)> {
+		self.protected_segment.push(k, v)
+	}
+
+	// Same as `push_new`, but if the probationary segment is full while the protected one has room, its least
+	// recently used pair is moved to the protected segment first. This fills both segments without promotions.
+	pub(crate) fn fill(&mut self, k: K, v: V) -> Option<(K, V)> {
+		if self.probationary_segment.len() == self.probationary_segment.cap().get()
+			&& self.protected_segment.len() < self.protected_segment.cap().get()
+		{
+			if let Some((k, v)) = self.probationary_segment.pop_lru() {
+				self.protected_segment.push(k, v);
+			}
 		}
 
 		self.probationary_segment.push(k, v)
 	}
 
+	#[cfg(test)]
 	pub(crate) fn get<'a, Q>(&'a mut self, k: &Q) -> Option<&'a V>
 	where
 		K: Borrow<Q>,
 		Q: Hash + Eq + ?Sized,
 	{
-		if let Some((k, v)) = self.probationary_segment.pop_entry(k) {
-			if let Some((k, v)) = self.protected_segment.push(k, v) {
+		self.get_mut_located(k).map(|(_, v)| &*v)
+	}
+
+	// Looks the key up, promoting it if it's stored in the probationary segment. Also returns the segment the
+	// key was found in before it was promoted.
+	pub(crate) fn get_mut_located<'a, Q>(&'a mut self, k: &Q) -> Option<(Segment, &'a mut V)>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		let segment = self.promote_on_access(k);
+		self.protected_segment.get_mut(k).map(|v| (segment, v))
+	}
+
+	// Promotes the key if it's stored in the probationary segment. Returns the segment the key was in.
+	fn promote_on_access<Q>(&mut self, k: &Q) -> Segment
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		match self.promote(k) {
+			true => Segment::Probationary,
+			false => Segment::Protected,
+		}
+	}
+
+	// Moves the key from the probationary to the protected segment if it's stored in the former, demoting the
+	// protected segment's least recently used unmarked pair if it's full. Returns whether the key was promoted.
+	pub(crate) fn promote<Q>(&mut self, k: &Q) -> bool
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		let (k, v) = match self.probationary_segment.pop_entry(k) {
+			Some(pair) => pair,
+			None => return false,
+		};
+
+		if self.protected_segment.len() == self.protected_segment.cap().get() {
+			if let Some((k, v)) = self.pop_demotion_victim() {
 				self.probationary_segment.push(k, v);
 			}
 		}
+		self.protected_segment.push(k, v);
+		self.debug_assert_capacity_invariant();
+
+		true
+	}
+
+	// Pops the least recently used protected pair that isn't marked. Marked pairs that are passed over are moved
+	// to the front of the segment. If every pair is marked, the least recently used one is popped and unmarked.
+	fn pop_demotion_victim(&mut self) -> Option<(K, V)> {
+		if !self.marked_keys.is_empty() {
+			for _ in 0..self.protected_segment.len() {
+				let (k, v) = self.protected_segment.pop_lru()?;
+				if !self.marked_keys.contains(&k) {
+					return Some((k, v));
+				}
+				self.protected_segment.push(k, v);
+			}
+		}
 
-		self.protected_segment.get(k)
+		let (k, v) = self.protected_segment.pop_lru()?;
+		self.unmark(&k);
+		Some((k, v))
 	}
 
-	pub(crate) fn get_mut<'a, Q>(&'a mut self, k: &Q) -> Option<&'a mut V>
+	// Marks a stored key so it's never chosen for demotion, promoting it if it's stored in the probationary
+	// segment. Fails if the key isn't stored, or if marking it would leave no protected pair to demote.
+	//
+	// The mark needs an owned key, so the stored one is cloned. Looking it up by iterating doesn't change its
+	// recency, and a promoted key is found right away at the front of the protected segment.
+	pub(crate) fn protect<Q>(&mut self, k: &Q) -> bool
+	where
+		K: Borrow<Q> + Clone,
+		Q: Hash + Eq + ?Sized,
+	{
+		if self.marked_keys.contains(k) {
+			return true;
+		}
+		if !self.contains(k) || self.marked_keys.len() + 1 >= self.protected_segment.cap().get() {
+			return false;
+		}
+
+		self.promote(k);
+		let (k, _) = self
+			.protected_segment
+			.iter()
+			.find(|(key, _)| (*key).borrow() == k)
+			.expect("key is stored in the protected segment");
+		self.marked_keys.insert(k.clone());
+		true
+	}
+
+	#[inline]
+	fn unmark<Q>(&mut self, k: &Q)
 	where
 		K: Borrow<Q>,
 		Q: Hash + Eq + ?Sized,
 	{
-		if let Some((k, v)) = self.probationary_segment.pop_entry(k) {
-			if let Some((k, v)) = self.protected_segment.push(k, v) {
-				self.probationary_segment.push(k, v);
-			}
+		if !self.marked_keys.is_empty() {
+			self.marked_keys.remove(k);
 		}
+	}
 
-		self.protected_segment.get_mut(k)
+	// Like `get_mut_located`, but only moves the key to the front of the segment it's stored in instead of
+	// promoting it.
+	pub(crate) fn get_mut_without_promotion<'a, Q>(
+		&'a mut self,
+		k: &Q,
+	) -> Option<(Segment, &'a mut V)>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		match self.probationary_segment.get_mut(k) {
+			Some(v) => Some((Segment::Probationary, v)),
+			None => self
+				.protected_segment
+				.get_mut(k)
+				.map(|v| (Segment::Protected, v)),
+		}
 	}
 
 	pub(crate) fn peek<'a, Q>(&'a self, k: &Q) -> Option<&'a V>
@@ -124,6 +253,32 @@ impl<K: Hash + Eq, V> SlruCache<K, V> {
 		}
 	}
 
+	pub(crate) fn is_protected<Q>(&self, k: &Q) -> bool
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		self.protected_segment.contains(k)
+	}
+
+	// Returns the segment of the key and its position within it, counting from the most recently used pair,
+	// along with the length of that segment.
+	pub(crate) fn rank<Q>(&self, k: &Q) -> Option<(Segment, usize, usize)>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		[
+			(Segment::Probationary, &self.probationary_segment),
+			(Segment::Protected, &self.protected_segment),
+		]
+		.into_iter()
+		.find_map(|(segment_kind, segment)| {
+			let rank = segment.iter().position(|(key, _)| key.borrow() == k)?;
+			Some((segment_kind, rank, segment.len()))
+		})
+	}
+
 	pub(crate) fn pop<Q>(&mut self, k: &Q) -> Option<V>
 	where
 		K: Borrow<Q>,
@@ -131,7 +286,10 @@ impl<K: Hash + Eq, V> SlruCache<K, V> {
 	{
 		match self.probationary_segment.pop(k) {
 			Some(v) => Some(v),
-			None => self.protected_segment.pop(k),
+			None => {
+				self.unmark(k);
+				self.protected_segment.pop(k)
+			}
 		}
 	}
 
@@ -142,55 +300,268 @@ impl<K: Hash + Eq, V> SlruCache<K, V> {
 	{
 		match self.probationary_segment.pop_entry(k) {
 			Some(v) => Some(v),
-			None => self.protected_segment.pop_entry(k),
+			None => {
+				self.unmark(k);
+				self.protected_segment.pop_entry(k)
+			}
+		}
+	}
+
+	// Replaces the value of a stored key with the one `f` makes of it and moves the pair to the front of its
+	// segment, without promoting it. Does nothing if the key isn't stored.
+	pub(crate) fn update_with<F: FnOnce(V) -> V>(&mut self, k: &K, f: F) {
+		for segment in [&mut self.probationary_segment, &mut self.protected_segment] {
+			if let Some((k, v)) = segment.pop_entry(k) {
+				segment.push(k, f(v));
+				return;
+			}
 		}
 	}
 
 	pub(crate) fn pop_lru(&mut self) -> Option<(K, V)> {
 		match self.probationary_segment.pop_lru() {
 			Some((k, v)) => Some((k, v)),
-			None => self.protected_segment.pop_lru(),
+			None => {
+				let (k, v) = self.protected_segment.pop_lru()?;
+				self.unmark(&k);
+				Some((k, v))
+			}
 		}
 	}
 
+	pub(crate) fn pop_lru_probationary(&mut self) -> Option<(K, V)> {
+		self.probationary_segment.pop_lru()
+	}
+
 	pub(crate) fn len(&self) -> usize {
 		self.probationary_segment.len() + self.protected_segment.len()
 	}
 
+	#[cfg(feature = "serde")]
+	#[inline]
+	pub(crate) fn probationary_ratio(&self) -> f64 {
+		self.probationary_ratio
+	}
+
+	#[inline]
+	pub(crate) fn probationary_len(&self) -> usize {
+		self.probationary_segment.len()
+	}
+
+	#[inline]
+	pub(crate) fn protected_len(&self) -> usize {
+		self.protected_segment.len()
+	}
+
 	pub(crate) fn cap(&self) -> usize {
 		self.probationary_segment.cap().get() + self.protected_segment.cap().get()
 	}
 
-	pub(crate) fn resize(&mut self, cap: usize) {
-		let f64_cap = cap as f64;
-		let probationary_cap =
-			NonZeroUsize::new(cmp::max(1, (f64_cap * 0.2) as usize)).expect("non zero size");
-		let protected_cap =
-			NonZeroUsize::new(cmp::max(1, cap - probationary_cap.get())).expect("non zero size");
+	// Checks that neither segment holds more entries than its capacity. Only runs in debug builds.
+	#[inline]
+	pub(crate) fn debug_assert_capacity_invariant(&self) {
+		debug_assert!(self.probationary_segment.len() <= self.probationary_segment.cap().get());
+		debug_assert!(self.protected_segment.len() <= self.protected_segment.cap().get());
+	}
 
+	// Returns whether resizing to `cap` would keep every entry, counting the ones demoted out of the protected
+	// segment.
+	pub(crate) fn fits(&self, cap: usize) -> bool {
+		let (probationary_cap, protected_cap) = segment_caps(cap, self.probationary_ratio);
+		let demoted = self
+			.protected_segment
+			.len()
+			.saturating_sub(protected_cap.get());
+
+		self.probationary_segment.len() + demoted <= probationary_cap.get()
+	}
+
+	// Returns the entries that were discarded because they didn't fit anymore.
+	pub(crate) fn resize(&mut self, cap: usize) -> Vec<(K, V)> {
+		let (probationary_cap, protected_cap) = segment_caps(cap, self.probationary_ratio);
+
+		let mut evicted = Vec::new();
+		while self.probationary_segment.len() > probationary_cap.get() {
+			evicted.extend(self.probationary_segment.pop_lru());
+		}
 		self.probationary_segment.resize(probationary_cap);
+
+		// Entries that don't fit into the protected segment anymore are demoted instead of being discarded.
+		// Marked entries are only demoted once every unmarked one is.
+		while self.protected_segment.len() > protected_cap.get() {
+			if let Some((k, v)) = self.pop_demotion_victim() {
+				evicted.extend(self.probationary_segment.push(k, v));
+			}
+		}
 		self.protected_segment.resize(protected_cap);
+
+		// Drops the marks of the least recently used marked entries until there's room for an unmarked one.
+		while self.marked_keys.len() >= protected_cap.get() {
+			let marked_keys = &mut self.marked_keys;
+			match self
+				.protected_segment
+				.iter()
+				.rev()
+				.find(|(k, _)| marked_keys.contains(*k))
+			{
+				Some((k, _)) => marked_keys.remove(k),
+				None => break,
+			};
+		}
+
+		evicted
+	}
+
+	// Changes the share of the protected segment and resizes both segments to `cap` with the new split.
+	// Returns the entries that were discarded because they didn't fit anymore.
+	pub(crate) fn set_protected_ratio(&mut self, ratio: f64, cap: usize) -> Vec<(K, V)> {
+		self.probationary_ratio = 1.0 - ratio;
+		self.resize(cap)
+	}
+
+	#[cfg(test)]
+	pub(crate) fn segment_caps(&self) -> (usize, usize) {
+		(
+			self.probationary_segment.cap().get(),
+			self.protected_segment.cap().get(),
+		)
+	}
+
+	// Keeps only the entries for which `f` returns `true`. Survivors keep their segment and recency order.
+	pub(crate) fn retain<F>(&mut self, mut f: F)
+	where
+		F: FnMut(&K, &mut V) -> bool,
+	{
+		retain_lru(&mut self.probationary_segment, &mut f);
+		retain_lru(&mut self.protected_segment, &mut f);
+
+		let protected_segment = &self.protected_segment;
+		self.marked_keys.retain(|k| protected_segment.contains(k));
 	}
 
 	pub(crate) fn clear(&mut self) {
 		self.probationary_segment.clear();
 		self.protected_segment.clear();
+		self.marked_keys.clear();
 	}
 
-	pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+	pub(crate) fn iter(&self) -> Iter<'_, K, V> {
 		self.probationary_segment
 			.iter()
 			.chain(self.protected_segment.iter())
 	}
+
+	// Visits the probationary segment first, then the protected one, like `iter`.
+	pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+		self.probationary_segment
+			.iter_mut()
+			.chain(self.protected_segment.iter_mut())
+	}
+
+	// Same as `iter`, but also yields the segment of every entry.
+	pub(crate) fn iter_located(&self) -> impl Iterator<Item = (Segment, &K, &V)> {
+		let probationary = self
+			.probationary_segment
+			.iter()
+			.map(|(k, v)| (Segment::Probationary, k, v));
+		let protected = self
+			.protected_segment
+			.iter()
+			.map(|(k, v)| (Segment::Protected, k, v));
+
+		probationary.chain(protected)
+	}
+}
+
+impl<K: Hash + Eq + Clone, V: Clone, S: BuildHasher + Clone> SlruCache<K, V, S> {
+	// Deep copies both segments, rebuilding them with `hash_builder`.
+	pub(crate) fn clone_with_hasher(&self, hash_builder: S) -> Self {
+		Self {
+			probationary_segment: clone_lru(&self.probationary_segment, hash_builder.clone()),
+			protected_segment: clone_lru(&self.protected_segment, hash_builder),
+			probationary_ratio: self.probationary_ratio,
+			marked_keys: self.marked_keys.clone(),
+		}
+	}
+}
+
+// Splits the capacity of the main cache into the capacities of the probationary and the protected segment.
+fn segment_caps(cap: usize, probationary_ratio: f64) -> (NonZeroUsize, NonZeroUsize) {
+	let f64_cap = cap as f64;
+	// The probationary segment never takes the last slot, which the protected segment needs at least.
+	let probationary_cap = cmp::min(
+		(f64_cap * probationary_ratio) as usize,
+		cap.saturating_sub(1),
+	);
+	let probationary_cap = NonZeroUsize::new(cmp::max(1, probationary_cap)).expect("non zero size");
+	let protected_cap = NonZeroUsize::new(cmp::max(1, cap.saturating_sub(probationary_cap.get())))
+		.expect("non zero size");
+
+	(probationary_cap, protected_cap)
+}
+
+// Rotates the whole cache once, pushing survivors back to the front, so their recency order is preserved.
+pub(crate) fn retain_lru<K, V, S, F>(cache: &mut LruCache<K, V, S>, f: &mut F)
+where
+	K: Hash + Eq,
+	S: BuildHasher,
+	F: FnMut(&K, &mut V) -> bool,
+{
+	for _ in 0..cache.len() {
+		if let Some((k, mut v)) = cache.pop_lru() {
+			if f(&k, &mut v) {
+				cache.push(k, v);
+			}
+		}
+	}
+}
+
+// Same rotation as `retain_lru`, but removes the pairs for which `f` returns `true` and returns them, least
+// recently used first.
+pub(crate) fn extract_lru<K, V, S, F>(cache: &mut LruCache<K, V, S>, f: &mut F) -> Vec<(K, V)>
+where
+	K: Hash + Eq,
+	S: BuildHasher,
+	F: FnMut(&K, &V) -> bool,
+{
+	let mut extracted = Vec::new();
+	for _ in 0..cache.len() {
+		if let Some((k, v)) = cache.pop_lru() {
+			if f(&k, &v) {
+				extracted.push((k, v));
+			} else {
+				cache.push(k, v);
+			}
+		}
+	}
+
+	extracted
+}
+
+// Copies the pairs into a new cache with the same capacity and recency order. `LruCache` only implements
+// `Clone` for its default hasher and doesn't expose the hasher it was built with, so it's passed in.
+pub(crate) fn clone_lru<K, V, S>(cache: &LruCache<K, V, S>, hash_builder: S) -> LruCache<K, V, S>
+where
+	K: Hash + Eq + Clone,
+	V: Clone,
+	S: BuildHasher,
+{
+	let mut cloned = LruCache::with_hasher(cache.cap(), hash_builder);
+	for (k, v) in cache.iter().rev() {
+		cloned.push(k.clone(), v.clone());
+	}
+
+	cloned
 }
 
 #[cfg(test)]
 mod tests {
-	use super::SlruCache;
+	use super::{SlruCache, DEFAULT_PROBATIONARY_RATIO};
+	use std::collections::hash_map::RandomState;
 
 	#[test]
 	fn store_and_retrieve_items() {
-		let mut cache = SlruCache::new(10);
+		let mut cache = SlruCache::new(10, DEFAULT_PROBATIONARY_RATIO, RandomState::new());
 		cache.push(1, "one");
 		cache.push(2, "two");
 		assert_eq!(cache.get(&1), Some(&"one"));
@@ -199,7 +570,7 @@ mod tests {
 
 	#[test]
 	fn store_retrieve_and_pop_items() {
-		let mut cache = SlruCache::new(10);
+		let mut cache = SlruCache::new(10, DEFAULT_PROBATIONARY_RATIO, RandomState::new());
 		cache.push(1, "one");
 		cache.push(2, "two");
 		assert_eq!(cache.get(&1), Some(&"one"));
@@ -220,7 +591,7 @@ mod tests {
 
 	#[test]
 	fn check_if_lru_is_correct() {
-		let mut cache = SlruCache::new(25);
+		let mut cache = SlruCache::new(25, DEFAULT_PROBATIONARY_RATIO, RandomState::new());
 		cache.push(1, "one");
 		cache.push(2, "two");
 		cache.push(3, "three");
@@ -249,7 +620,7 @@ mod tests {
 
 	#[test]
 	fn check_if_cap_and_len_are_correct() {
-		let mut cache = SlruCache::new(10);
+		let mut cache = SlruCache::new(10, DEFAULT_PROBATIONARY_RATIO, RandomState::new());
 		cache.push(1, "one");
 		cache.push(2, "two");
 		assert_eq!(cache.cap(), 10);
@@ -269,9 +640,26 @@ mod tests {
 		assert_eq!(cache.len(), 3);
 	}
 
+	#[test]
+	fn resize_demotes_protected_overflow() {
+		let mut cache = SlruCache::new(100, DEFAULT_PROBATIONARY_RATIO, RandomState::new());
+		for i in 0..50 {
+			cache.push(i, i);
+			cache.get(&i);
+		}
+		assert_eq!(cache.protected_segment.len(), 50);
+
+		let evicted = cache.resize(20);
+		assert_eq!(evicted.len(), 30);
+		assert_eq!(cache.protected_segment.len(), 16);
+		assert_eq!(cache.probationary_segment.len(), 4);
+		assert_eq!(cache.probationary_segment.peek_lru(), Some((&30, &30)));
+		assert_eq!(cache.protected_segment.peek_lru(), Some((&34, &34)));
+	}
+
 	#[test]
 	fn clear_cache() {
-		let mut cache = SlruCache::new(10);
+		let mut cache = SlruCache::new(10, DEFAULT_PROBATIONARY_RATIO, RandomState::new());
 		cache.push(1, "one");
 		cache.push(2, "two");
 		assert_eq!(cache.get(&1), Some(&"one"));
@@ -0,0 +1,84 @@
+use crate::Segment;
+
+/// Counters describing how effective the cache is, returned by [`WTinyLfuCache::stats`].
+///
+/// [`WTinyLfuCache::stats`]: crate::WTinyLfuCache::stats
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+	/// Number of lookups that found the key in the cache.
+	pub hits: u64,
+	/// Number of lookups that didn't find the key in the cache.
+	pub misses: u64,
+	/// Number of hits served by the window cache.
+	pub window_hits: u64,
+	/// Number of hits served by the main cache.
+	pub main_hits: u64,
+	/// Number of window cache victims that were moved into the main cache, either because it had room or
+	/// because they won the admission contest.
+	pub admissions: u64,
+	/// Number of window cache victims that lost the admission contest and were evicted.
+	pub rejections: u64,
+	/// Number of pairs evicted by admission decisions, counting both rejected window cache victims and main
+	/// cache victims pushed out by an admission. This includes the decisions `resize` makes for the pairs that
+	/// don't fit into the window cache anymore, but not pairs dropped by the main cache shrinking or `clear`.
+	pub evictions: u64,
+}
+
+impl Stats {
+	#[inline]
+	pub(crate) fn record_lookup(&mut self, hit: Option<Segment>) {
+		match hit {
+			Some(Segment::Window) => {
+				self.hits += 1;
+				self.window_hits += 1;
+			}
+			Some(Segment::Probationary | Segment::Protected) => {
+				self.hits += 1;
+				self.main_hits += 1;
+			}
+			None => self.misses += 1,
+		}
+	}
+
+	// Records a window cache victim that entered the main cache, and whether that pushed a main cache victim
+	// out.
+	#[inline]
+	pub(crate) fn record_admission(&mut self, evicted: bool) {
+		self.admissions += 1;
+		self.evictions += evicted as u64;
+	}
+
+	#[inline]
+	pub(crate) fn record_rejection(&mut self) {
+		self.rejections += 1;
+		self.evictions += 1;
+	}
+
+	/// Returns the share of all lookups that found the key in the cache.
+	/// Returns `0.0` if there were no lookups.
+	pub fn hit_ratio(&self) -> f64 {
+		self.ratio(self.hits)
+	}
+
+	/// Returns the share of all lookups that were served by the window cache.
+	/// Returns `0.0` if there were no lookups.
+	pub fn window_hit_ratio(&self) -> f64 {
+		self.ratio(self.window_hits)
+	}
+
+	/// Returns the share of all lookups that were served by the main cache.
+	/// Returns `0.0` if there were no lookups.
+	pub fn main_hit_ratio(&self) -> f64 {
+		self.ratio(self.main_hits)
+	}
+
+	#[inline]
+	fn ratio(&self, hits: u64) -> f64 {
+		let lookups = self.hits + self.misses;
+		if lookups == 0 {
+			return 0.0;
+		}
+
+		hits as f64 / lookups as f64
+	}
+}
@@ -0,0 +1,773 @@
+use crate::slru::{clone_lru, extract_lru, retain_lru};
+use lru::LruCache;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::iter;
+use std::mem;
+use std::num::NonZeroUsize;
+use std::slice;
+
+/// Eviction policy of the window cache, selected with [`WTinyLfuCache::with_window_policy`].
+///
+/// [`WTinyLfuCache::with_window_policy`]: crate::WTinyLfuCache::with_window_policy
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WindowPolicy {
+	/// Evicts the least recently used pair. Accesses move a pair to the front of the window.
+	#[default]
+	Lru,
+	/// Evicts the oldest pair. Accesses and updates don't change the eviction order, so every pair leaves the
+	/// window in insertion order.
+	Fifo,
+	/// Evicts pairs in insertion order, but gives pairs that were accessed since the eviction hand last
+	/// passed them a second chance. Unlike LRU, retained pairs keep their position instead of being moved to
+	/// the front.
+	Sieve,
+}
+
+pub(crate) enum WindowCache<K, V, S> {
+	Lru(LruCache<K, V, S>),
+	Fifo(LruCache<K, V, S>),
+	Sieve(SieveCache<K, V, S>),
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> WindowCache<K, V, S> {
+	pub(crate) fn new(policy: WindowPolicy, cap: NonZeroUsize, hash_builder: S) -> Self {
+		match policy {
+			WindowPolicy::Lru => Self::Lru(LruCache::with_hasher(cap, hash_builder)),
+			WindowPolicy::Fifo => Self::Fifo(LruCache::with_hasher(cap, hash_builder)),
+			WindowPolicy::Sieve => Self::Sieve(SieveCache::new(cap, hash_builder)),
+		}
+	}
+
+	#[cfg(feature = "serde")]
+	pub(crate) fn policy(&self) -> WindowPolicy {
+		match self {
+			Self::Lru(_) => WindowPolicy::Lru,
+			Self::Fifo(_) => WindowPolicy::Fifo,
+			Self::Sieve(_) => WindowPolicy::Sieve,
+		}
+	}
+
+	// Inserts a key that isn't stored yet, without looking it up first.
+	pub(crate) fn push_new(&mut self, k: K, v: V) -> Option<(K, V)> {
+		match self {
+			Self::Lru(cache) | Self::Fifo(cache) => cache.push(k, v),
+			Self::Sieve(cache) => cache.push(k, v),
+		}
+	}
+
+	#[cfg(test)]
+	pub(crate) fn get<'a, Q>(&'a mut self, k: &Q) -> Option<&'a V>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		match self {
+			Self::Lru(cache) => cache.get(k),
+			Self::Fifo(cache) => cache.peek(k),
+			Self::Sieve(cache) => cache.get(k),
+		}
+	}
+
+	pub(crate) fn get_mut<'a, Q>(&'a mut self, k: &Q) -> Option<&'a mut V>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		match self {
+			Self::Lru(cache) => cache.get_mut(k),
+			Self::Fifo(cache) => cache.peek_mut(k),
+			Self::Sieve(cache) => cache.get_mut(k),
+		}
+	}
+
+	pub(crate) fn peek<'a, Q>(&'a self, k: &Q) -> Option<&'a V>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		match self {
+			Self::Lru(cache) | Self::Fifo(cache) => cache.peek(k),
+			Self::Sieve(cache) => cache.peek(k),
+		}
+	}
+
+	pub(crate) fn peek_mut<'a, Q>(&'a mut self, k: &Q) -> Option<&'a mut V>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		match self {
+			Self::Lru(cache) | Self::Fifo(cache) => cache.peek_mut(k),
+			Self::Sieve(cache) => cache.peek_mut(k),
+		}
+	}
+
+	// Returns the pair that would be evicted next.
+	pub(crate) fn peek_lru(&self) -> Option<(&K, &V)> {
+		match self {
+			Self::Lru(cache) | Self::Fifo(cache) => cache.peek_lru(),
+			Self::Sieve(cache) => cache.peek_victim(),
+		}
+	}
+
+	pub(crate) fn contains<Q>(&self, k: &Q) -> bool
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		match self {
+			Self::Lru(cache) | Self::Fifo(cache) => cache.contains(k),
+			Self::Sieve(cache) => cache.contains(k),
+		}
+	}
+
+	// Returns the position of the key counting from the pair that would be evicted last.
+	pub(crate) fn rank<Q>(&self, k: &Q) -> Option<usize>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		self.iter().position(|(key, _)| key.borrow() == k)
+	}
+
+	pub(crate) fn pop<Q>(&mut self, k: &Q) -> Option<V>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		self.pop_entry(k).map(|(_, v)| v)
+	}
+
+	pub(crate) fn pop_entry<Q>(&mut self, k: &Q) -> Option<(K, V)>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		match self {
+			Self::Lru(cache) | Self::Fifo(cache) => cache.pop_entry(k),
+			Self::Sieve(cache) => cache.pop_entry(k),
+		}
+	}
+
+	// Replaces the value of a stored key with the one `f` makes of it and reinserts the pair as the newest one.
+	// Does nothing if the key isn't stored.
+	pub(crate) fn update_with<F: FnOnce(V) -> V>(&mut self, k: &K, f: F) {
+		if let Some((k, v)) = self.pop_entry(k) {
+			self.push_new(k, f(v));
+		}
+	}
+
+	// Evicts the pair that would be evicted next.
+	pub(crate) fn pop_lru(&mut self) -> Option<(K, V)> {
+		match self {
+			Self::Lru(cache) | Self::Fifo(cache) => cache.pop_lru(),
+			Self::Sieve(cache) => cache.evict(),
+		}
+	}
+
+	pub(crate) fn len(&self) -> usize {
+		match self {
+			Self::Lru(cache) | Self::Fifo(cache) => cache.len(),
+			Self::Sieve(cache) => cache.len(),
+		}
+	}
+
+	pub(crate) fn cap(&self) -> NonZeroUsize {
+		match self {
+			Self::Lru(cache) | Self::Fifo(cache) => cache.cap(),
+			Self::Sieve(cache) => cache.cap(),
+		}
+	}
+
+	pub(crate) fn resize(&mut self, cap: NonZeroUsize) {
+		match self {
+			Self::Lru(cache) | Self::Fifo(cache) => cache.resize(cap),
+			Self::Sieve(cache) => cache.resize(cap),
+		}
+	}
+
+	pub(crate) fn retain<F>(&mut self, mut f: F)
+	where
+		F: FnMut(&K, &mut V) -> bool,
+	{
+		match self {
+			Self::Lru(cache) | Self::Fifo(cache) => retain_lru(cache, &mut f),
+			Self::Sieve(cache) => cache.retain(f),
+		}
+	}
+
+	// Removes the pairs for which `f` returns `true` and returns them in eviction order. Survivors keep their
+	// position.
+	pub(crate) fn extract<F>(&mut self, mut f: F) -> Vec<(K, V)>
+	where
+		F: FnMut(&K, &V) -> bool,
+	{
+		match self {
+			Self::Lru(cache) | Self::Fifo(cache) => extract_lru(cache, &mut f),
+			Self::Sieve(cache) => cache.extract(f),
+		}
+	}
+
+	pub(crate) fn clear(&mut self) {
+		match self {
+			Self::Lru(cache) | Self::Fifo(cache) => cache.clear(),
+			Self::Sieve(cache) => cache.clear(),
+		}
+	}
+
+	// Returns the value of the most recently inserted or used pair, without changing the eviction order.
+	pub(crate) fn peek_newest_mut(&mut self) -> Option<&mut V> {
+		match self {
+			Self::Lru(cache) | Self::Fifo(cache) => cache.iter_mut().next().map(|(_, v)| v),
+			Self::Sieve(cache) => cache.peek_newest_mut(),
+		}
+	}
+
+	pub(crate) fn iter(&self) -> Iter<'_, K, V> {
+		match self {
+			Self::Lru(cache) | Self::Fifo(cache) => Iter::Lru(cache.iter()),
+			Self::Sieve(cache) => Iter::Sieve(cache.iter()),
+		}
+	}
+
+	// Visits the pairs in `iter` order for LRU and FIFO windows and in no particular order for SIEVE.
+	pub(crate) fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+		match self {
+			Self::Lru(cache) | Self::Fifo(cache) => IterMut::Lru(cache.iter_mut()),
+			Self::Sieve(cache) => IterMut::Sieve(cache.iter_mut()),
+		}
+	}
+}
+
+impl<K: Hash + Eq + Clone, V: Clone, S: BuildHasher + Clone> WindowCache<K, V, S> {
+	// Deep copies the window, rebuilding LRU and FIFO windows with `hash_builder`. A SIEVE window is cloned
+	// with its own hasher, since its buckets are keyed by the hashes it computed, and keeps its visited bits
+	// and the position of the hand.
+	pub(crate) fn clone_with_hasher(&self, hash_builder: S) -> Self {
+		match self {
+			Self::Lru(cache) => Self::Lru(clone_lru(cache, hash_builder)),
+			Self::Fifo(cache) => Self::Fifo(clone_lru(cache, hash_builder)),
+			Self::Sieve(cache) => Self::Sieve(cache.clone()),
+		}
+	}
+}
+
+pub(crate) enum Iter<'a, K, V> {
+	Lru(lru::Iter<'a, K, V>),
+	Sieve(SieveIter<'a, K, V>),
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+	type Item = (&'a K, &'a V);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		match self {
+			Self::Lru(iter) => iter.next(),
+			Self::Sieve(iter) => iter.next(),
+		}
+	}
+}
+
+pub(crate) enum IterMut<'a, K, V> {
+	Lru(lru::IterMut<'a, K, V>),
+	Sieve(SieveIterMut<'a, K, V>),
+}
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+	type Item = (&'a K, &'a mut V);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		match self {
+			Self::Lru(iter) => iter.next(),
+			Self::Sieve(iter) => iter.next(),
+		}
+	}
+}
+
+const NIL: usize = usize::MAX;
+
+#[derive(Clone)]
+struct SieveNode<K, V> {
+	k: K,
+	v: V,
+	visited: bool,
+	// Neighbour towards the newest node.
+	newer: usize,
+	// Neighbour towards the oldest node.
+	older: usize,
+	// Next node whose key has the same hash.
+	next_in_bucket: usize,
+}
+
+// SIEVE cache. Nodes are kept in insertion order in a doubly linked list backed by a slab of nodes, and
+// looked up through a map from key hashes to chains of nodes with that hash, so keys don't have to be cloned.
+#[derive(Clone)]
+pub(crate) struct SieveCache<K, V, S> {
+	nodes: Vec<Option<SieveNode<K, V>>>,
+	free_nodes: Vec<usize>,
+	buckets: HashMap<u64, usize>,
+	hash_builder: S,
+	newest: usize,
+	oldest: usize,
+	hand: usize,
+	len: usize,
+	cap: NonZeroUsize,
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> SieveCache<K, V, S> {
+	pub(crate) fn new(cap: NonZeroUsize, hash_builder: S) -> Self {
+		Self {
+			nodes: Vec::new(),
+			free_nodes: Vec::new(),
+			buckets: HashMap::new(),
+			hash_builder,
+			newest: NIL,
+			oldest: NIL,
+			hand: NIL,
+			len: 0,
+			cap,
+		}
+	}
+
+	pub(crate) fn push(&mut self, k: K, v: V) -> Option<(K, V)> {
+		let hash = self.hash_builder.hash_one(&k);
+		if let Some(idx) = self.find(hash, &k) {
+			let node = self.node_mut(idx);
+			node.visited = true;
+			let old_v = mem::replace(&mut node.v, v);
+			return Some((k, old_v));
+		}
+
+		let evicted = if self.len >= self.cap.get() {
+			self.evict()
+		} else {
+			None
+		};
+
+		let node = SieveNode {
+			k,
+			v,
+			visited: false,
+			newer: NIL,
+			older: self.newest,
+			next_in_bucket: self.buckets.get(&hash).copied().unwrap_or(NIL),
+		};
+		let idx = match self.free_nodes.pop() {
+			Some(idx) => {
+				self.nodes[idx] = Some(node);
+				idx
+			}
+			None => {
+				self.nodes.push(Some(node));
+				self.nodes.len() - 1
+			}
+		};
+
+		if self.newest != NIL {
+			self.node_mut(self.newest).newer = idx;
+		} else {
+			self.oldest = idx;
+		}
+		self.newest = idx;
+		self.buckets.insert(hash, idx);
+		self.len += 1;
+
+		evicted
+	}
+
+	#[cfg(test)]
+	pub(crate) fn get<'a, Q>(&'a mut self, k: &Q) -> Option<&'a V>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		self.get_mut(k).map(|v| &*v)
+	}
+
+	pub(crate) fn get_mut<'a, Q>(&'a mut self, k: &Q) -> Option<&'a mut V>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		let idx = self.find(self.hash_builder.hash_one(k), k)?;
+		let node = self.node_mut(idx);
+		node.visited = true;
+		Some(&mut node.v)
+	}
+
+	pub(crate) fn peek<'a, Q>(&'a self, k: &Q) -> Option<&'a V>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		let idx = self.find(self.hash_builder.hash_one(k), k)?;
+		Some(&self.node(idx).v)
+	}
+
+	pub(crate) fn peek_mut<'a, Q>(&'a mut self, k: &Q) -> Option<&'a mut V>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		let idx = self.find(self.hash_builder.hash_one(k), k)?;
+		Some(&mut self.node_mut(idx).v)
+	}
+
+	// Returns the pair `evict` would remove, without clearing any visited bits.
+	pub(crate) fn peek_victim(&self) -> Option<(&K, &V)> {
+		let start = self.hand_or_oldest();
+		if start == NIL {
+			return None;
+		}
+
+		let mut idx = start;
+		loop {
+			let node = self.node(idx);
+			if !node.visited {
+				return Some((&node.k, &node.v));
+			}
+
+			idx = if node.newer != NIL {
+				node.newer
+			} else {
+				self.oldest
+			};
+			if idx == start {
+				// Every node was visited, the hand clears them all and comes back to where it started.
+				let node = self.node(start);
+				return Some((&node.k, &node.v));
+			}
+		}
+	}
+
+	pub(crate) fn contains<Q>(&self, k: &Q) -> bool
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		self.find(self.hash_builder.hash_one(k), k).is_some()
+	}
+
+	pub(crate) fn pop_entry<Q>(&mut self, k: &Q) -> Option<(K, V)>
+	where
+		K: Borrow<Q>,
+		Q: Hash + Eq + ?Sized,
+	{
+		let hash = self.hash_builder.hash_one(k);
+		let idx = self.find(hash, k)?;
+		Some(self.remove(hash, idx))
+	}
+
+	// Moves the hand from the oldest towards the newest node, clearing visited bits, and removes the first
+	// node that wasn't visited.
+	pub(crate) fn evict(&mut self) -> Option<(K, V)> {
+		let mut idx = self.hand_or_oldest();
+		if idx == NIL {
+			return None;
+		}
+
+		loop {
+			let node = self.node_mut(idx);
+			if !node.visited {
+				break;
+			}
+
+			node.visited = false;
+			idx = if node.newer != NIL {
+				node.newer
+			} else {
+				self.oldest
+			};
+		}
+
+		self.hand = self.node(idx).newer;
+		let hash = self.hash_builder.hash_one(&self.node(idx).k);
+		Some(self.remove(hash, idx))
+	}
+
+	#[inline]
+	pub(crate) fn len(&self) -> usize {
+		self.len
+	}
+
+	#[inline]
+	pub(crate) fn cap(&self) -> NonZeroUsize {
+		self.cap
+	}
+
+	pub(crate) fn resize(&mut self, cap: NonZeroUsize) {
+		while self.len > cap.get() {
+			self.evict();
+		}
+
+		self.cap = cap;
+	}
+
+	// Survivors keep their position and visited bit.
+	pub(crate) fn retain<F>(&mut self, mut f: F)
+	where
+		F: FnMut(&K, &mut V) -> bool,
+	{
+		let mut idx = self.oldest;
+		while idx != NIL {
+			let node = self.node_mut(idx);
+			let newer = node.newer;
+			if !f(&node.k, &mut node.v) {
+				let hash = self.hash_builder.hash_one(&self.node(idx).k);
+				self.remove(hash, idx);
+			}
+
+			idx = newer;
+		}
+	}
+
+	// Like `retain`, but removes the pairs for which `f` returns `true` and returns them, oldest first.
+	pub(crate) fn extract<F>(&mut self, mut f: F) -> Vec<(K, V)>
+	where
+		F: FnMut(&K, &V) -> bool,
+	{
+		let mut extracted = Vec::new();
+		let mut idx = self.oldest;
+		while idx != NIL {
+			let node = self.node(idx);
+			let newer = node.newer;
+			if f(&node.k, &node.v) {
+				let hash = self.hash_builder.hash_one(&node.k);
+				extracted.push(self.remove(hash, idx));
+			}
+
+			idx = newer;
+		}
+
+		extracted
+	}
+
+	pub(crate) fn clear(&mut self) {
+		self.nodes.clear();
+		self.free_nodes.clear();
+		self.buckets.clear();
+		self.newest = NIL;
+		self.oldest = NIL;
+		self.hand = NIL;
+		self.len = 0;
+	}
+
+	pub(crate) fn peek_newest_mut(&mut self) -> Option<&mut V> {
+		if self.newest == NIL {
+			return None;
+		}
+
+		Some(&mut self.node_mut(self.newest).v)
+	}
+
+	// Visits pairs from the newest to the oldest one.
+	pub(crate) fn iter(&self) -> SieveIter<'_, K, V> {
+		SieveIter {
+			nodes: &self.nodes,
+			idx: self.newest,
+		}
+	}
+
+	// Visits the pairs in slab order, since the list can't be walked while handing out mutable references.
+	pub(crate) fn iter_mut(&mut self) -> SieveIterMut<'_, K, V> {
+		SieveIterMut {
+			nodes: self.nodes.iter_mut().flatten(),
+		}
+	}
+
+	#[inline]
+	fn hand_or_oldest(&self) -> usize {
+		if self.hand != NIL {
+			self.hand
+		} else {
+			self.oldest
+		}
+	}
+
+	fn find<Q>(&self, hash: u64, k: &Q) -> Option<usize>
+	where
+		K: Borrow<Q>,
+		Q: Eq + ?Sized,
+	{
+		let mut idx = *self.buckets.get(&hash)?;
+		while idx != NIL {
+			let node = self.node(idx);
+			if node.k.borrow() == k {
+				return Some(idx);
+			}
+
+			idx = node.next_in_bucket;
+		}
+
+		None
+	}
+
+	fn remove(&mut self, hash: u64, idx: usize) -> (K, V) {
+		let node = self.nodes[idx].take().expect("node exists");
+		self.free_nodes.push(idx);
+		self.len -= 1;
+
+		if self.hand == idx {
+			self.hand = node.newer;
+		}
+
+		if node.newer != NIL {
+			self.node_mut(node.newer).older = node.older;
+		} else {
+			self.newest = node.older;
+		}
+		if node.older != NIL {
+			self.node_mut(node.older).newer = node.newer;
+		} else {
+			self.oldest = node.newer;
+		}
+
+		let head = self.buckets[&hash];
+		if head == idx {
+			if node.next_in_bucket != NIL {
+				self.buckets.insert(hash, node.next_in_bucket);
+			} else {
+				self.buckets.remove(&hash);
+			}
+		} else {
+			let mut prev = head;
+			while self.node(prev).next_in_bucket != idx {
+				prev = self.node(prev).next_in_bucket;
+			}
+			self.node_mut(prev).next_in_bucket = node.next_in_bucket;
+		}
+
+		(node.k, node.v)
+	}
+
+	#[inline]
+	fn node(&self, idx: usize) -> &SieveNode<K, V> {
+		self.nodes[idx].as_ref().expect("node exists")
+	}
+
+	#[inline]
+	fn node_mut(&mut self, idx: usize) -> &mut SieveNode<K, V> {
+		self.nodes[idx].as_mut().expect("node exists")
+	}
+}
+
+pub(crate) struct SieveIter<'a, K, V> {
+	nodes: &'a [Option<SieveNode<K, V>>],
+	idx: usize,
+}
+
+impl<'a, K, V> Iterator for SieveIter<'a, K, V> {
+	type Item = (&'a K, &'a V);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let node = self.nodes.get(self.idx)?.as_ref()?;
+		self.idx = node.older;
+		Some((&node.k, &node.v))
+	}
+}
+
+pub(crate) struct SieveIterMut<'a, K, V> {
+	nodes: iter::Flatten<slice::IterMut<'a, Option<SieveNode<K, V>>>>,
+}
+
+impl<'a, K, V> Iterator for SieveIterMut<'a, K, V> {
+	type Item = (&'a K, &'a mut V);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.nodes.next().map(|node| (&node.k, &mut node.v))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{WindowCache, WindowPolicy};
+	use std::collections::hash_map::RandomState;
+	use std::num::NonZeroUsize;
+
+	fn window(policy: WindowPolicy) -> WindowCache<i32, i32, RandomState> {
+		let mut window =
+			WindowCache::new(policy, NonZeroUsize::new(3).unwrap(), RandomState::new());
+		for i in 1..=3 {
+			assert_eq!(window.push_new(i, i), None);
+		}
+
+		window
+	}
+
+	#[test]
+	fn lru_evicts_least_recently_used() {
+		let mut window = window(WindowPolicy::Lru);
+		window.get(&1);
+		assert_eq!(window.peek_lru(), Some((&2, &2)));
+		assert_eq!(window.push_new(4, 4), Some((2, 2)));
+	}
+
+	#[test]
+	fn fifo_evicts_oldest() {
+		let mut window = window(WindowPolicy::Fifo);
+		window.get(&1);
+		*window.get_mut(&1).unwrap() = 10;
+		assert_eq!(window.peek_lru(), Some((&1, &10)));
+		assert_eq!(window.push_new(4, 4), Some((1, 10)));
+		assert_eq!(window.push_new(5, 5), Some((2, 2)));
+	}
+
+	#[test]
+	fn sieve_gives_visited_pairs_a_second_chance() {
+		let mut window = window(WindowPolicy::Sieve);
+		window.get(&1);
+		assert_eq!(window.peek_lru(), Some((&2, &2)));
+		assert_eq!(window.push_new(4, 4), Some((2, 2)));
+
+		// The hand continues where it stopped instead of starting over from the oldest pair.
+		assert_eq!(window.push_new(5, 5), Some((3, 3)));
+		window.get(&4);
+		assert_eq!(window.push_new(6, 6), Some((5, 5)));
+
+		// The first pair's visited bit was cleared by the first eviction.
+		assert_eq!(window.push_new(7, 7), Some((1, 1)));
+
+		let mut keys = window.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+		assert_eq!(&keys, &[7, 6, 4]);
+		keys.sort();
+		assert!(keys.iter().all(|k| window.contains(k)));
+	}
+
+	#[test]
+	fn sieve_pops_and_resizes() {
+		let mut window = window(WindowPolicy::Sieve);
+		assert_eq!(window.pop(&2), Some(2));
+		assert_eq!(window.len(), 2);
+		assert!(!window.contains(&2));
+		assert_eq!(window.push_new(4, 4), None);
+
+		window.get(&3);
+		window.resize(NonZeroUsize::new(1).unwrap());
+		assert_eq!(window.len(), 1);
+		assert_eq!(window.peek(&3), Some(&3));
+		assert_eq!(window.peek(&4), None);
+
+		window.clear();
+		assert_eq!(window.len(), 0);
+		assert_eq!(window.peek_lru(), None);
+		assert_eq!(window.pop_lru(), None);
+	}
+
+	#[test]
+	fn retain_keeps_eviction_order() {
+		for policy in [WindowPolicy::Lru, WindowPolicy::Fifo, WindowPolicy::Sieve] {
+			let mut window = window(policy);
+			window.retain(|k, v| {
+				*v *= 10;
+				*k != 2
+			});
+			assert_eq!(window.len(), 2);
+			assert!(!window.contains(&2));
+			assert_eq!(window.pop_lru(), Some((1, 10)));
+			assert_eq!(window.pop_lru(), Some((3, 30)));
+		}
+	}
+}
@@ -0,0 +1,54 @@
+// Checks that read operations don't allocate. This lives in its own test binary because it replaces the
+// global allocator.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use wtinylfu::WTinyLfuCache;
+
+struct CountingAllocator;
+
+thread_local! {
+	static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+		System.alloc(layout)
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		System.dealloc(ptr, layout)
+	}
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations_during<F: FnOnce()>(f: F) -> usize {
+	let before = ALLOCATIONS.with(Cell::get);
+	f();
+	ALLOCATIONS.with(Cell::get) - before
+}
+
+#[test]
+fn reads_dont_allocate() {
+	let mut cache: WTinyLfuCache<u64, u64> = WTinyLfuCache::new(1000, 1000);
+	for i in 0..1000 {
+		cache.put(i, i);
+	}
+	// Promote every key the main cache holds, so the lookups below don't move pairs between segments.
+	for i in 0..1000 {
+		cache.get(&i);
+	}
+
+	let allocations = allocations_during(|| {
+		for _ in 0..10 {
+			for i in 0..2000 {
+				cache.get(&i);
+				assert_eq!(cache.contains(&i), cache.peek(&i).is_some());
+			}
+		}
+	});
+	assert_eq!(allocations, 0);
+}